@@ -0,0 +1,434 @@
+use std::{collections::HashMap, f32::consts::PI, path::Path, time::Duration};
+
+use bevy::{
+    audio::{play_queued_audio_system, AudioOutput, AudioSink, Decodable, Sample, Source},
+    reflect::TypeUuid,
+};
+use bevy_egui::EguiContext;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use crate::*;
+
+// sounds this far to the side of the camera play entirely in one ear
+const PAN_DISTANCE: f32 = 600.0;
+// sounds fade out between these distances from the camera
+const NEAR: f32 = 300.0;
+const FAR: f32 = 1400.0;
+// how fast music layers fade towards where they should be, per second
+const MUSIC_FADE: f32 = 1.5;
+// live enemies it takes for the combat layer to be at full volume
+const FULL_COMBAT: f32 = 12.0;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    pub master: f32,
+    pub music: f32,
+    pub sfx: f32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            master: 0.8,
+            music: 0.6,
+            sfx: 0.8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, EnumIter)]
+pub enum Sfx {
+    Shoot,
+    Hit,
+    EnemyDeath,
+    PlayerHurt,
+    Pickup,
+    Explosion,
+    UiClick,
+}
+
+impl Sfx {
+    fn path(&self) -> &'static str {
+        match self {
+            Sfx::Shoot => "sounds/shoot.ogg",
+            Sfx::Hit => "sounds/hit.ogg",
+            Sfx::EnemyDeath => "sounds/enemy_death.ogg",
+            Sfx::PlayerHurt => "sounds/player_hurt.ogg",
+            Sfx::Pickup => "sounds/pickup.ogg",
+            Sfx::Explosion => "sounds/explosion.ogg",
+            Sfx::UiClick => "sounds/ui_click.ogg",
+        }
+    }
+
+    fn volume(&self) -> f32 {
+        match self {
+            Sfx::Shoot => 0.4,
+            Sfx::Hit => 0.5,
+            Sfx::UiClick => 0.6,
+            _ => 1.0,
+        }
+    }
+
+    // copies of the same sound that can play over each other, and roughly how long each lasts
+    fn voices(&self) -> (usize, f32) {
+        match self {
+            Sfx::Shoot => (6, 0.15),
+            Sfx::Hit => (8, 0.15),
+            Sfx::EnemyDeath => (6, 0.4),
+            Sfx::PlayerHurt => (2, 0.3),
+            Sfx::Pickup => (2, 0.5),
+            Sfx::Explosion => (3, 0.8),
+            Sfx::UiClick => (2, 0.1),
+        }
+    }
+
+    // playback speed, and so pitch, is off by up to this much either way
+    fn pitch_variance(&self) -> f32 {
+        match self {
+            Sfx::UiClick => 0.0,
+            Sfx::Explosion => 0.05,
+            _ => 0.1,
+        }
+    }
+}
+
+/// Plays a sound effect, panned and faded by where it is relative to the camera. No
+/// position means it plays centred at full volume, like UI sounds.
+pub struct PlaySound {
+    pub sfx: Sfx,
+    pub position: Option<Vec2>,
+}
+
+impl PlaySound {
+    pub fn at(sfx: Sfx, position: Vec2) -> Self {
+        return PlaySound {
+            sfx,
+            position: Some(position),
+        };
+    }
+}
+
+/// Wraps a decoder so the left and right ears get their own volume. Mono sounds are spread
+/// out to stereo first.
+pub struct Panned<I: Source>
+where
+    I::Item: Sample,
+{
+    input: I,
+    gains: [f32; 2],
+    mono: bool,
+    channel: u16,
+    // the second ear's copy of the last mono sample
+    pending: Option<I::Item>,
+}
+
+impl<I: Source> Iterator for Panned<I>
+where
+    I::Item: Sample,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        if self.mono {
+            if let Some(sample) = self.pending.take() {
+                return Some(sample.amplify(self.gains[1]));
+            }
+            let sample = self.input.next()?;
+            self.pending = Some(sample);
+            return Some(sample.amplify(self.gains[0]));
+        }
+        let sample = self.input.next()?;
+        let gain = self.gains.get(self.channel as usize).copied().unwrap_or(1.0);
+        self.channel = (self.channel + 1) % self.input.channels().max(1);
+        return Some(sample.amplify(gain));
+    }
+}
+
+impl<I: Source> Source for Panned<I>
+where
+    I::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        let len = self.input.current_frame_len()?;
+        return Some(if self.mono { len * 2 } else { len });
+    }
+
+    fn channels(&self) -> u16 {
+        return if self.mono { 2 } else { self.input.channels() };
+    }
+
+    fn sample_rate(&self) -> u32 {
+        return self.input.sample_rate();
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        return self.input.total_duration();
+    }
+}
+
+/// A loaded sound with its panning baked in. One is made per play and dropped once the
+/// audio output has picked it up.
+#[derive(TypeUuid)]
+#[uuid = "c6a1f0d4-3b8e-4f52-9e7a-1d2b4c5e6f70"]
+pub struct PannedSound {
+    source: AudioSource,
+    gains: [f32; 2],
+}
+
+impl Decodable for PannedSound {
+    type Decoder = Panned<<AudioSource as Decodable>::Decoder>;
+    type DecoderItem = <AudioSource as Decodable>::DecoderItem;
+
+    fn decoder(&self) -> Self::Decoder {
+        let input = self.source.decoder();
+        return Panned {
+            mono: input.channels() == 1,
+            input,
+            gains: self.gains,
+            channel: 0,
+            pending: None,
+        };
+    }
+}
+
+/// Equal-power gains for the left and right ear, `pan` going from -1 (left) to 1 (right).
+/// The squares always add up to 1, so dead centre is about 0.71 in each ear.
+pub fn pan_gains(pan: f32) -> [f32; 2] {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * PI / 4.0;
+    return [angle.cos(), angle.sin()];
+}
+
+/// How loud something `distance` away from the camera is, from 1 down to 0.
+pub fn attenuation(distance: f32) -> f32 {
+    return 1.0 - ((distance - NEAR) / (FAR - NEAR)).clamp(0.0, 1.0);
+}
+
+#[derive(Resource, Default)]
+pub struct Sounds {
+    sfx: HashMap<Sfx, Handle<AudioSource>>,
+    // calm, combat and boss, all playing at once and faded in and out
+    music: Vec<Handle<AudioSource>>,
+}
+
+/// When each playing copy of a sound should be done, to keep to its voice limit.
+#[derive(Resource, Default)]
+pub struct Voices(HashMap<Sfx, Vec<f32>>);
+
+#[derive(Resource, Default)]
+pub struct Music {
+    layers: Vec<(Handle<AudioSink>, f32)>,
+}
+
+/// Sound effects and music. Headless builds that leave out bevy's audio plugin get a
+/// silent no-op: sounds can still be asked for, they just never play.
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PlaySound>();
+        if !app.world.contains_resource::<Assets<AudioSource>>() {
+            return;
+        }
+        app.init_non_send_resource::<AudioOutput<PannedSound>>()
+            .add_asset::<PannedSound>()
+            .init_resource::<Audio<PannedSound>>()
+            .init_resource::<Sounds>()
+            .init_resource::<Voices>()
+            .init_resource::<Music>()
+            .add_startup_system(load)
+            .add_system(play_sounds)
+            .add_system(on_damage)
+            .add_system(ui_clicks)
+            .add_system(play_music)
+            .add_system_to_stage(CoreStage::PostUpdate, play_queued_audio_system::<PannedSound>);
+    }
+}
+
+// files that aren't there yet are skipped rather than logged as errors, that sound just
+// stays silent
+fn load(asset_server: Res<AssetServer>, mut sounds: ResMut<Sounds>) {
+    let exists = |path: &str| asset_server.asset_io().is_file(Path::new(path));
+    for sfx in Sfx::iter().filter(|sfx| exists(sfx.path())) {
+        sounds.sfx.insert(sfx, asset_server.load(sfx.path()));
+    }
+    // the layers only play together, so it's all of them or no music
+    let music = ["music/calm.ogg", "music/combat.ogg", "music/boss.ogg"];
+    if music.iter().all(|path| exists(path)) {
+        sounds.music = music.iter().map(|path| asset_server.load(*path)).collect();
+    }
+}
+
+fn play_sounds(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    sounds: Res<Sounds>,
+    sources: Res<Assets<AudioSource>>,
+    mut panned: ResMut<Assets<PannedSound>>,
+    audio: Res<Audio<PannedSound>>,
+    mut voices: ResMut<Voices>,
+    mut requests: EventReader<PlaySound>,
+    cameras: Query<&GlobalTransform, With<CameraRig>>,
+) {
+    let now = time.elapsed_seconds();
+    let camera = cameras.get_single().map(|t| t.translation().truncate()).unwrap_or_default();
+    let mut rng = rand::thread_rng();
+
+    for request in requests.iter() {
+        // only play what's actually loaded, or bevy keeps it queued forever
+        let source = match sounds.sfx.get(&request.sfx).and_then(|h| sources.get(h)) {
+            Some(source) => source,
+            None => continue,
+        };
+
+        let (gains, distance_volume) = match request.position {
+            Some(position) => {
+                let offset = position - camera;
+                (pan_gains(offset.x / PAN_DISTANCE), attenuation(offset.length()))
+            }
+            // same level as something right in front of the camera
+            None => (pan_gains(0.0), 1.0),
+        };
+        let volume = settings.audio.master * settings.audio.sfx * request.sfx.volume() * distance_volume;
+        if volume <= 0.01 {
+            continue;
+        }
+
+        let (max_voices, length) = request.sfx.voices();
+        let playing = voices.0.entry(request.sfx).or_default();
+        playing.retain(|&end| end > now);
+        if playing.len() >= max_voices {
+            continue;
+        }
+        playing.push(now + length);
+
+        let variance = request.sfx.pitch_variance();
+        let speed = 1.0 + if variance > 0.0 { rng.gen_range(-variance..=variance) } else { 0.0 };
+        let sound = panned.add(PannedSound {
+            source: source.clone(),
+            gains,
+        });
+        audio.play_with_settings(sound, PlaybackSettings { repeat: false, volume, speed });
+    }
+}
+
+/// Hits on enemies and on players sound different.
+fn on_damage(
+    mut damages: EventReader<DamageDealt>,
+    mut sounds: EventWriter<PlaySound>,
+    players: Query<(), With<Player>>,
+    transforms: Query<&GlobalTransform>,
+) {
+    for damage in damages.iter() {
+        if let Ok(transform) = transforms.get(damage.target) {
+            let sfx = if players.contains(damage.target) { Sfx::PlayerHurt } else { Sfx::Hit };
+            sounds.send(PlaySound::at(sfx, transform.translation().truncate()));
+        }
+    }
+}
+
+fn ui_clicks(mut egui_context: ResMut<EguiContext>, mut sounds: EventWriter<PlaySound>) {
+    let ctx = egui_context.ctx_mut();
+    if ctx.input().pointer.any_click() && ctx.is_pointer_over_area() {
+        sounds.send(PlaySound {
+            sfx: Sfx::UiClick,
+            position: None,
+        });
+    }
+}
+
+/// How loud the calm, combat and boss layers should be. The second half of a boss fight
+/// brings the combat layer in on top of the boss one.
+pub fn music_levels(enemies: usize, boss_health: Option<f32>) -> [f32; 3] {
+    if let Some(health) = boss_health {
+        let combat = if health < 0.5 { 1.0 } else { 0.0 };
+        return [0.0, combat, 1.0];
+    }
+    let combat = (enemies as f32 / FULL_COMBAT).min(1.0);
+    return [1.0 - 0.7 * combat, combat, 0.0];
+}
+
+fn play_music(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    sounds: Res<Sounds>,
+    sources: Res<Assets<AudioSource>>,
+    mut panned: ResMut<Assets<PannedSound>>,
+    audio: Res<Audio<PannedSound>>,
+    sinks: Res<Assets<AudioSink>>,
+    mut music: ResMut<Music>,
+    enemies: Query<&Enemy>,
+) {
+    // start every layer together once they've all loaded, so they stay in time
+    if music.layers.is_empty() {
+        let loaded: Vec<&AudioSource> = sounds.music.iter().filter_map(|h| sources.get(h)).collect();
+        if loaded.is_empty() || loaded.len() < sounds.music.len() {
+            return;
+        }
+        for source in loaded {
+            let sound = panned.add(PannedSound {
+                source: source.clone(),
+                gains: [1.0, 1.0],
+            });
+            let sink = audio.play_with_settings(sound, PlaybackSettings::LOOP.with_volume(0.0));
+            music.layers.push((sinks.get_handle(sink), 0.0));
+        }
+    }
+
+    let boss_health = enemies
+        .iter()
+        .find(|e| e.boss && e.health > 0.0)
+        .map(|e| e.health / e.max_health as f32);
+    let targets = music_levels(enemies.iter().filter(|e| e.health > 0.0).count(), boss_health);
+    let fade = (MUSIC_FADE * time.delta_seconds()).min(1.0);
+    let volume = settings.audio.master * settings.audio.music;
+    for ((sink, level), target) in music.layers.iter_mut().zip(targets) {
+        *level += (target - *level) * fade;
+        if let Some(sink) = sinks.get(sink) {
+            sink.set_volume(*level * volume);
+        }
+    }
+}
+
+#[test]
+fn panning_is_even_in_the_middle_and_one_sided_at_the_edges() {
+    let [left, right] = pan_gains(0.0);
+    assert!((left - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-4 && (right - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-4);
+    let [left, right] = pan_gains(-1.0);
+    assert!((left - 1.0).abs() < 1e-4 && right.abs() < 1e-4);
+    let [left, right] = pan_gains(1.0);
+    assert!(left.abs() < 1e-4 && (right - 1.0).abs() < 1e-4);
+    let [left, right] = pan_gains(5.0);
+    assert!(left.abs() < 1e-4 && (right - 1.0).abs() < 1e-4);
+}
+
+#[test]
+fn panning_keeps_the_power_and_moves_steadily() {
+    let mut last = pan_gains(-1.0);
+    for i in 1..=100 {
+        let [left, right] = pan_gains(-1.0 + i as f32 / 50.0);
+        assert!((left * left + right * right - 1.0).abs() < 1e-4, "{} {}", left, right);
+        // no jumps or flat spots anywhere across the sweep
+        assert!(left < last[0] && last[0] - left < 0.04, "{} -> {}", last[0], left);
+        assert!(right > last[1] && right - last[1] < 0.04, "{} -> {}", last[1], right);
+        last = [left, right];
+    }
+}
+
+#[test]
+fn sounds_fade_with_distance() {
+    assert_eq!(attenuation(0.0), 1.0);
+    assert_eq!(attenuation(FAR + 10.0), 0.0);
+    assert!(attenuation((NEAR + FAR) / 2.0) < 1.0);
+}
+
+#[test]
+fn music_follows_the_fight() {
+    assert_eq!(music_levels(0, None), [1.0, 0.0, 0.0]);
+    assert_eq!(music_levels(100, None)[1], 1.0);
+    assert_eq!(music_levels(3, Some(0.9)), [0.0, 0.0, 1.0]);
+    assert_eq!(music_levels(3, Some(0.3)), [0.0, 1.0, 1.0]);
+}
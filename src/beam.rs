@@ -0,0 +1,147 @@
+use crate::*;
+use input::{Action, ActionState};
+
+const BEAM_RANGE: f32 = 1200.0;
+// damage is dealt in ticks, like hazards, rather than every frame
+const BEAM_INTERVAL: f32 = 0.1;
+// fraction of the gun's damage per second the beam deals, since it can't miss
+const BEAM_EFFICIENCY: f32 = 0.6;
+
+/// What a player shoots with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Weapon {
+    #[default]
+    Gun,
+    Beam,
+}
+
+impl Weapon {
+    /// Crit chance and crit multiplier the weapon adds to the player's own.
+    pub fn crit_bonus(&self) -> (f32, f32) {
+        return match self {
+            Weapon::Gun => (0.0, 0.0),
+            // each tick is weak, so the beam makes up for it with more of them critting
+            Weapon::Beam => (0.1, 0.0),
+        };
+    }
+}
+
+/// The glowing beam drawn from a player while they fire it.
+#[derive(Component)]
+pub struct Beam {
+    owner: Entity,
+    clock: Timer,
+}
+
+/// Damage per second for a beam fired with these stats.
+pub fn beam_dps(stats: &Stats) -> f32 {
+    return stats.damage.value() / stats.fire_interval.value().max(0.01) * BEAM_EFFICIENCY;
+}
+
+pub fn make_beam_mesh() -> Mesh {
+    return shape::Quad::new(Vec2::ONE).into();
+}
+
+pub fn tick(
+    mut commands: Commands,
+    game: Res<Game>,
+    time: Res<Time>,
+    mut colors: ResMut<Assets<ColorMaterial>>,
+    mut damages: EventWriter<Damage>,
+    mut players: Query<(Entity, &mut Player, &ActionState)>,
+    mut beams: Query<(Entity, &mut Beam, &mut Transform, &mut Visibility)>,
+    targets: Query<&GlobalTransform, Or<(With<Enemy>, With<Prop>)>>,
+    rapier_ctx: Res<RapierContext>,
+) {
+    // every beam wielder gets one, kept around and hidden while they aren't firing
+    for (entity, player, _) in players.iter() {
+        if player.weapon == Weapon::Beam && !beams.iter().any(|(_, beam, _, _)| beam.owner == entity) {
+            commands.spawn((
+                ColorMesh2dBundle {
+                    mesh: game.handles.beam_mesh.clone(),
+                    material: colors.add(BEAM_GLOW.into()),
+                    visibility: Visibility { is_visible: false },
+                    ..default()
+                },
+                Beam {
+                    owner: entity,
+                    clock: Timer::from_seconds(BEAM_INTERVAL, TimerMode::Repeating),
+                },
+            ));
+        }
+    }
+
+    for (beam_entity, mut beam, mut transform, mut visibility) in beams.iter_mut() {
+        let (mut player, actions) = match players.get_mut(beam.owner) {
+            Ok((_, player, actions)) => (player, actions),
+            Err(_) => {
+                commands.entity(beam_entity).despawn();
+                continue;
+            }
+        };
+        visibility.is_visible = player.weapon == Weapon::Beam && !player.downed && actions.pressed(Action::Fire);
+        if !visibility.is_visible {
+            beam.clock.reset();
+            continue;
+        }
+
+        // walls are the only solid colliders, so they're all the ray can stop on
+        let origin = player.position;
+        let dir = player.direction.normalize_or_zero();
+        let length = match rapier_ctx.cast_ray(origin, dir, BEAM_RANGE, true, QueryFilter::default().exclude_sensors()) {
+            Some((_, toi)) => toi,
+            None => BEAM_RANGE,
+        };
+        let width = player.stats.shot_size.value() * 2.0;
+        let flicker = 1.0 + 0.15 * (time.elapsed_seconds_wrapped() * 60.0).sin();
+        *transform = Transform {
+            translation: (origin + dir * length / 2.0).extend(31.0),
+            rotation: Quat::from_rotation_z(dir.y.atan2(dir.x)),
+            scale: Vec3::new(length, width * flicker, 1.0),
+        };
+
+        if !beam.clock.tick(time.delta()).just_finished() {
+            continue;
+        }
+        // everything the beam touches, nearest first, up to the pierce count
+        let mut hits: Vec<(Entity, f32)> = vec![];
+        rapier_ctx.intersections_with_shape(
+            origin + dir * length / 2.0,
+            dir.y.atan2(dir.x),
+            &Collider::cuboid(length / 2.0, width / 2.0),
+            QueryFilter::default(),
+            |entity| {
+                if let Ok(target) = targets.get(entity) {
+                    hits.push((entity, (target.translation().truncate() - origin).dot(dir)));
+                }
+                true
+            },
+        );
+        hits.sort_by(|a, b| a.1.total_cmp(&b.1));
+        // each tick is one shot, rolled once for everything it touches
+        let pierce = (player.stats.piercing.value() as usize).max(1);
+        let (damage, crit) = player.stats.roll_damage();
+        // scale by how the roll compares to the base damage; a zeroed damage stat rolls zero
+        let amount = beam_dps(&player.stats) * BEAM_INTERVAL * damage / player.stats.damage.value().max(f32::EPSILON);
+        let shot = player.next_shot();
+        for (target, _) in hits.into_iter().take(pierce) {
+            damages.send(Damage {
+                target,
+                amount,
+                kind: DamageKind::Beam,
+                source: Some(beam.owner),
+                shot: Some(shot),
+                crit,
+                impulse: Vec2::ZERO,
+            });
+        }
+    }
+}
+
+#[test]
+fn beam_dps_follows_fire_rate() {
+    let mut player = Player::default();
+    let slow = beam_dps(&player.stats);
+    player.stats.fire_interval.multiply = 0.5;
+    assert!((beam_dps(&player.stats) - 2.0 * slow).abs() < 1e-3);
+}
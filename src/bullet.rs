@@ -1,5 +1,9 @@
 use crate::{physics_sprite::PhysicsSpriteBundle, *};
 
+const KNOCKBACK_SCALE: f32 = 5.0;
+// explosive shots deal this fraction of their damage to everything around the impact
+const EXPLOSION_DAMAGE: f32 = 0.5;
+
 #[derive(Component, Default)]
 pub struct Bullet {
     pub shooter: Option<Entity>,
@@ -7,16 +11,48 @@ pub struct Bullet {
     pub position: Vec2,
     pub velocity: Vec2,
     pub damage: f32,
+    pub crit: bool,
+    // which of the shooter's shots this is
+    pub shot: u32,
     pub radius: f32,
+    pub knockback: f32,
+    pub explosion_radius: f32,
+    pub chain_jumps: i32,
 
     pub piercing: i32,
     pub hit_enemies: Vec<Entity>,
 }
 
 impl Bullet {
+    /// Bigger, harder hitting shots shove enemies further.
+    pub fn impulse(&self) -> Vec2 {
+        return self.velocity.normalize_or_zero() * self.damage * (self.radius / 10.0) * self.knockback * KNOCKBACK_SCALE;
+    }
+
+    /// Blows up at `position` if it's an explosive shot, sparing `hit` which the shot itself
+    /// already damaged.
+    fn explode(&self, position: Vec2, hit: Option<Entity>, explosions: &mut EventWriter<Explosion>) {
+        if self.explosion_radius > 0.0 {
+            explosions.send(Explosion {
+                position,
+                radius: self.explosion_radius,
+                damage: self.damage * EXPLOSION_DAMAGE,
+                source: self.shooter,
+                hurts_players: false,
+                exclude: hit,
+            });
+        }
+    }
+
     pub fn update_position(&mut self, delta: &Time) {
         self.position += self.velocity * delta.delta_seconds();
     }
+
+    /// What the pool writes onto a bullet every time it's fired.
+    pub fn fired(self) -> (Bullet, Transform, Visibility) {
+        let transform = Transform::from_translation(self.position.extend(32.0));
+        return (self, transform, Visibility { is_visible: true });
+    }
 }
 
 #[derive(Default, Bundle)]
@@ -27,69 +63,199 @@ pub struct BulletBundle {
 }
 
 impl BulletBundle {
-    pub fn new(bullet: Bullet, mesh: Mesh2dHandle) -> Self {
+    /// The parts of a bullet that stay put while it's pooled. Fire it with [`Bullet::fired`].
+    pub fn new(radius: f32, mesh: Mesh2dHandle) -> Self {
         BulletBundle {
             sprite: PhysicsSpriteBundle {
-                collider: Collider::ball(bullet.radius),
+                collider: Collider::ball(radius),
                 sensor: Sensor,
                 mesh: ColorMesh2dBundle {
-                    transform: Transform {
-                        translation: bullet.position.extend(32.0f32),
-                        ..default()
-                    },
                     mesh: mesh,
                     ..default()
                 },
                 ..default()
             },
-            bullet: bullet,
+            bullet: Bullet::default(),
         }
     }
 }
 
+/// Everything `collider` runs into on its way from `start` to `start + travel`, nearest
+/// first, as the entity and how far along the way it was hit. Whatever is in `skip` is
+/// passed straight through.
+fn sweep<'a>(
+    rapier_ctx: &'a RapierContext,
+    start: Vec2,
+    travel: Vec2,
+    collider: &'a Collider,
+    skip: &[Entity],
+) -> impl Iterator<Item = (Entity, f32)> + 'a {
+    let mut skip = skip.to_vec();
+    // cast again past each hit until there's nothing left on the path
+    return std::iter::from_fn(move || {
+        let not_skipped = |entity: Entity| !skip.contains(&entity);
+        let filter = QueryFilter::default().predicate(&not_skipped);
+        let (entity, toi) = rapier_ctx.cast_shape(start, 0.0, travel, collider, 1.0, filter)?;
+        skip.push(entity);
+        return Some((entity, toi.toi));
+    });
+}
+
+/// Moves every bullet, then sweeps its shape along the path it just travelled so fast
+/// shots can't skip over anything. Hits are handled nearest first, so piercing shots
+/// use up their pierce count on the right enemies.
 pub fn tick(
     mut commands: Commands,
     time: Res<Time>,
-    mut game: ResMut<Game>,
-    mut bullets: Query<(Entity, &mut Bullet, &mut Transform, &Collider)>,
-    mut enemies: Query<(&mut Enemy, &mut Transform, &Collider, Without<Bullet>)>,
+    mut pool: ResMut<Pool<Bullet>>,
+    mut bullets: Query<(Entity, &mut Bullet, &mut Transform, &Collider), Without<Parked>>,
+    enemies: Query<(), With<Enemy>>,
+    mut damages: EventWriter<Damage>,
+    mut explosions: EventWriter<Explosion>,
+    mut chains: EventWriter<ChainLightning>,
+    mut particles: EventWriter<EmitParticles>,
+    walls: Query<(), With<Wall>>,
+    props: Query<(), With<Prop>>,
     rapier_ctx: Res<RapierContext>,
 ) {
     for (bullet_entity, mut bullet, mut transform, collider) in bullets.iter_mut() {
+        let start = bullet.position;
         bullet.update_position(time.as_ref());
+        let travel = bullet.position - start;
         *transform = Transform {
             translation: Vec3::new(bullet.position.x, bullet.position.y, 32.0f32),
             ..default()
         };
 
-        rapier_ctx.intersections_with_shape(
-            transform.translation.truncate(),
-            0.0,
-            collider,
-            QueryFilter::default(),
-            |entity| {
-                if let Ok(mut enemy) = enemies.get_mut(entity) {
-
-                    if let None = bullet.hit_enemies.iter().find(|&&x| x == entity){
-
-                        enemy.0.direction = bullet.velocity.normalize();
-                        enemy.0.health -= bullet.damage;
-                        if enemy.0.health <= 0.0 {
-                            game.kills += 1;
-                            game.player.score += enemy.0.max_health;
-                        }
-
-                        bullet.piercing -= 1;
-                        if bullet.piercing <= 0 {
-                            commands.entity(bullet_entity).despawn();
-                        }
-                        else{
-                            bullet.hit_enemies.push(entity)
-                        }
-                    }
+        // a reused bullet keeps its old collider, unless the shot size has changed since
+        let resized = (collider.as_ball().map(|b| b.radius()) != Some(bullet.radius)).then(|| Collider::ball(bullet.radius));
+        if let Some(ball) = &resized {
+            commands.entity(bullet_entity).insert(ball.clone());
+        }
+        let collider = resized.as_ref().unwrap_or(collider);
+
+        let skip = [&bullet.hit_enemies[..], &[bullet_entity]].concat();
+        for (entity, toi) in sweep(&rapier_ctx, start, travel, collider, &skip) {
+            let contact = start + travel * toi;
+            let impact = EmitParticles {
+                effect: "impact",
+                position: contact,
+                direction: -travel,
+            };
+
+            if walls.contains(entity) {
+                particles.send(impact);
+                bullet.explode(contact, None, &mut explosions);
+                pool.release(&mut commands, bullet_entity);
+                break;
+            }
+            if props.contains(entity) {
+                damages.send(Damage {
+                    target: entity,
+                    amount: bullet.damage,
+                    kind: DamageKind::Bullet,
+                    source: bullet.shooter,
+                    shot: Some(bullet.shot),
+                    crit: bullet.crit,
+                    impulse: Vec2::ZERO,
+                });
+                particles.send(impact);
+                bullet.explode(contact, Some(entity), &mut explosions);
+                pool.release(&mut commands, bullet_entity);
+                break;
+            }
+            if enemies.contains(entity) {
+                damages.send(Damage {
+                    target: entity,
+                    amount: bullet.damage,
+                    kind: DamageKind::Bullet,
+                    source: bullet.shooter,
+                    shot: Some(bullet.shot),
+                    crit: bullet.crit,
+                    impulse: bullet.impulse(),
+                });
+                particles.send(impact);
+                if bullet.chain_jumps > 0 {
+                    chains.send(ChainLightning {
+                        first: entity,
+                        jumps: bullet.chain_jumps,
+                        damage: bullet.damage,
+                        source: bullet.shooter,
+                    });
                 }
-                true
-            },
-        );
+                // piercing shots blow up on everything they pass through
+                bullet.explode(contact, Some(entity), &mut explosions);
+
+                bullet.piercing -= 1;
+                if bullet.piercing <= 0 {
+                    pool.release(&mut commands, bullet_entity);
+                    break;
+                }
+                bullet.hit_enemies.push(entity);
+            }
+        }
     }
 }
+
+#[cfg(test)]
+fn test_world(shapes: &[(Vec2, Collider)]) -> (App, Vec<Entity>) {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0));
+    let entities = shapes
+        .iter()
+        .map(|(pos, collider)| {
+            app.world
+                .spawn((collider.clone(), TransformBundle::from(Transform::from_translation(pos.extend(0.0)))))
+                .id()
+        })
+        .collect();
+    // the first update adds the colliders, the second puts them in the query pipeline
+    app.update();
+    app.update();
+    return (app, entities);
+}
+
+#[test]
+fn test_fast_bullets_hit_thin_walls() {
+    let (app, entities) = test_world(&[(vec2(200.0, 0.0), Collider::cuboid(2.0, 100.0))]);
+    let rapier_ctx = app.world.resource::<RapierContext>();
+
+    // a whole screen in one step, starting and ending well clear of the wall
+    let ball = Collider::ball(5.0);
+    let hits: Vec<_> = sweep(rapier_ctx, vec2(-500.0, 0.0), vec2(1000.0, 0.0), &ball, &[]).collect();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].0, entities[0]);
+    assert!((hits[0].1 - 0.693).abs() < 0.001, "{}", hits[0].1);
+}
+
+#[test]
+fn test_piercing_hits_come_in_contact_order() {
+    let (app, entities) = test_world(&[
+        (vec2(300.0, 0.0), Collider::ball(20.0)),
+        (vec2(100.0, 0.0), Collider::ball(20.0)),
+        (vec2(200.0, 0.0), Collider::ball(20.0)),
+    ]);
+    let rapier_ctx = app.world.resource::<RapierContext>();
+
+    let ball = Collider::ball(5.0);
+    let hits: Vec<_> = sweep(rapier_ctx, vec2(-100.0, 0.0), vec2(600.0, 0.0), &ball, &[]).collect();
+    let order: Vec<_> = hits.iter().map(|hit| hit.0).collect();
+    assert_eq!(order, vec![entities[1], entities[2], entities[0]]);
+    assert!(hits.windows(2).all(|w| w[0].1 < w[1].1), "{:?}", hits);
+}
+
+#[test]
+fn test_enemies_already_hit_are_passed_through() {
+    let (app, entities) = test_world(&[
+        (vec2(100.0, 0.0), Collider::ball(20.0)),
+        (vec2(200.0, 0.0), Collider::ball(20.0)),
+    ]);
+    let rapier_ctx = app.world.resource::<RapierContext>();
+
+    let ball = Collider::ball(5.0);
+    let hit_enemies = vec![entities[0]];
+    let hits: Vec<_> = sweep(rapier_ctx, vec2(0.0, 0.0), vec2(300.0, 0.0), &ball, &hit_enemies).collect();
+    assert_eq!(hits.iter().map(|hit| hit.0).collect::<Vec<_>>(), vec![entities[1]]);
+}
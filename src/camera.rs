@@ -0,0 +1,409 @@
+use bevy::{
+    core_pipeline::clear_color::ClearColorConfig,
+    render::{
+        camera::{CameraProjection, RenderTarget, ScalingMode},
+        primitives::Frustum,
+    },
+};
+use rand::Rng;
+
+use crate::*;
+
+// world units visible vertically at a zoom of 1
+pub const VIEW_HEIGHT: f32 = 800.0;
+const FOLLOW_SPEED: f32 = 5.0;
+const ZOOM_SPEED: f32 = 2.0;
+// how far ahead of the players, towards where they aim, the camera looks
+const LOOK_AHEAD: f32 = 80.0;
+// room kept around the players when zooming out to fit everyone in co-op
+const FRAME_MARGIN: f32 = 250.0;
+
+const TRAUMA_DECAY: f32 = 1.5;
+const MAX_SHAKE_OFFSET: f32 = 20.0;
+const MAX_SHAKE_ANGLE: f32 = 0.05;
+
+/// Adds trauma to the camera. Shake scales with trauma squared so small hits stay subtle.
+pub struct ScreenShake(pub f32);
+
+#[derive(Component)]
+pub struct CameraRig {
+    pub position: Vec2,
+    pub view_height: f32,
+    pub trauma: f32,
+}
+
+impl Default for CameraRig {
+    fn default() -> Self {
+        return CameraRig {
+            position: Vec2::ZERO,
+            view_height: VIEW_HEIGHT,
+            trauma: 0.0,
+        };
+    }
+}
+
+pub fn make_camera() -> Camera2dBundle {
+    let far = 1000.0;
+    // we want 0 to be "closest" and +far to be "farthest" in 2d, so we offset
+    // the camera's translation by far and use a right handed coordinate system
+    let projection = OrthographicProjection {
+        far,
+        scaling_mode: ScalingMode::FixedVertical(VIEW_HEIGHT),
+        ..Default::default()
+    };
+    let transform = Transform::from_xyz(0.0, 0.0, far - 0.1);
+    let view_projection = projection.get_projection_matrix() * transform.compute_matrix().inverse();
+    let frustum = Frustum::from_view_projection(
+        &view_projection,
+        &transform.translation,
+        &transform.back(),
+        projection.far(),
+    );
+    Camera2dBundle {
+        projection,
+        frustum,
+        transform,
+        camera_2d: Camera2d{ clear_color: ClearColorConfig::Custom(Color::BLACK)},
+        camera: Camera {
+            hdr: true,
+            ..default()
+        },
+        ..default()
+    }
+}
+
+/// Maps a position in viewport pixels (origin bottom left, y up) to the world.
+///
+/// `projection` is the camera's projection matrix; rotation, zoom and translation all
+/// come from `camera_transform`, so this works for any 2d camera.
+pub fn viewport_to_world_2d(
+    viewport_pos: Vec2,
+    viewport_size: Vec2,
+    camera_transform: &GlobalTransform,
+    projection: Mat4,
+) -> Option<Vec2> {
+    if viewport_size.x <= 0.0 || viewport_size.y <= 0.0 {
+        return None;
+    }
+    let ndc = viewport_pos * 2.0 / viewport_size - Vec2::ONE;
+    let ndc_to_world = camera_transform.compute_matrix() * projection.inverse();
+    let world = ndc_to_world.project_point3(ndc.extend(1.0));
+    return (!world.is_nan()).then_some(world.truncate());
+}
+
+/// Inverse of [`viewport_to_world_2d`].
+pub fn world_to_viewport_2d(
+    world_pos: Vec2,
+    viewport_size: Vec2,
+    camera_transform: &GlobalTransform,
+    projection: Mat4,
+) -> Option<Vec2> {
+    let world_to_ndc = projection * camera_transform.compute_matrix().inverse();
+    let ndc = world_to_ndc.project_point3(world_pos.extend(0.0));
+    return (!ndc.is_nan()).then_some((ndc.truncate() + Vec2::ONE) / 2.0 * viewport_size);
+}
+
+/// Converts a window cursor position (logical pixels, origin bottom left) into world space
+/// through `camera`. Returns `None` if the camera doesn't draw to `window` or the point is
+/// outside its viewport, so callers with several cameras can just try each in turn.
+pub fn screen_to_world(
+    window: &Window,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    screen_pos: Vec2,
+) -> Option<Vec2> {
+    if camera.target != RenderTarget::Window(window.id()) {
+        return None;
+    }
+    // viewport rects are measured from the top left, cursor positions from the bottom left
+    let (min, max) = camera.logical_viewport_rect()?;
+    let viewport_pos = vec2(screen_pos.x - min.x, screen_pos.y - (window.height() - max.y));
+    let viewport_size = max - min;
+    if viewport_pos.cmplt(Vec2::ZERO).any() || viewport_pos.cmpgt(viewport_size).any() {
+        return None;
+    }
+    return viewport_to_world_2d(viewport_pos, viewport_size, camera_transform, camera.projection_matrix());
+}
+
+/// Converts a cursor position through the highest priority active camera it's over, so a
+/// picture-in-picture camera on top of the main view takes the cursor while it's inside it.
+pub fn cursor_to_world<'a>(
+    window: &Window,
+    cameras: impl IntoIterator<Item = (&'a Camera, &'a GlobalTransform)>,
+    screen_pos: Vec2,
+) -> Option<Vec2> {
+    let mut cameras: Vec<_> = cameras.into_iter().filter(|(camera, _)| camera.is_active).collect();
+    cameras.sort_by_key(|(camera, _)| -camera.priority);
+    return cameras
+        .into_iter()
+        .find_map(|(camera, camera_transform)| screen_to_world(window, camera, camera_transform, screen_pos));
+}
+
+/// Inverse of [`screen_to_world`]. Points off screen are still returned, outside the window.
+pub fn world_to_screen(
+    window: &Window,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    world_pos: Vec2,
+) -> Option<Vec2> {
+    if camera.target != RenderTarget::Window(window.id()) {
+        return None;
+    }
+    let (min, max) = camera.logical_viewport_rect()?;
+    let viewport_pos = world_to_viewport_2d(world_pos, max - min, camera_transform, camera.projection_matrix())?;
+    return Some(vec2(viewport_pos.x + min.x, viewport_pos.y + window.height() - max.y));
+}
+
+/// Keeps the camera centre inside the map, or centred on it when the view is wider than the map.
+fn clamp_to_map(center: Vec2, half_view: Vec2, half_map: Vec2) -> Vec2 {
+    let clamp_axis = |c: f32, half_view: f32, half_map: f32| {
+        if half_view >= half_map {
+            0.0
+        } else {
+            c.clamp(-half_map + half_view, half_map - half_view)
+        }
+    };
+    return vec2(
+        clamp_axis(center.x, half_view.x, half_map.x),
+        clamp_axis(center.y, half_view.y, half_map.y),
+    );
+}
+
+/// Where the camera should head and how much world height it should show to keep every
+/// player in `players` (position, aim direction) on screen. `None` with nobody to follow.
+fn frame_players(players: &[(Vec2, Vec2)], aspect: f32, map_dims: Vec2) -> Option<(Vec2, f32)> {
+    if players.is_empty() {
+        return None;
+    }
+    let count = players.len() as f32;
+    let centroid = players.iter().map(|&(position, _)| position).sum::<Vec2>() / count;
+    let look = players.iter().map(|&(_, direction)| direction).sum::<Vec2>() / count * LOOK_AHEAD;
+
+    // zoom out just enough to keep everyone on screen, but never past the whole map
+    let (min, max) = players.iter().fold((centroid, centroid), |(min, max), &(position, _)| {
+        (min.min(position), max.max(position))
+    });
+    let spread = max - min + Vec2::splat(FRAME_MARGIN);
+    let base_height = VIEW_HEIGHT.min(map_dims.y);
+    let height = base_height
+        .max(spread.y)
+        .max(spread.x / aspect)
+        .min(map_dims.y.max(base_height));
+    return Some((centroid + look, height));
+}
+
+/// Trauma after `dt` seconds with `added` more from this frame's shakes, capped at 1.
+fn next_trauma(trauma: f32, added: f32, dt: f32) -> f32 {
+    return ((trauma + added).min(1.0) - TRAUMA_DECAY * dt).max(0.0);
+}
+
+pub fn tick(
+    time: Res<Time>,
+    windows: Res<Windows>,
+    arena: Res<Arena>,
+    mut shakes: EventReader<ScreenShake>,
+    players: Query<&Player>,
+    mut camera: Query<(&mut CameraRig, &mut Transform, &mut OrthographicProjection)>,
+) {
+    let (mut rig, mut transform, mut projection) = match camera.get_single_mut() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let aspect = match windows.get_primary() {
+        Some(window) if window.height() > 0.0 => window.width() / window.height(),
+        _ => 1.0,
+    };
+    let dt = time.delta_seconds();
+    let map_dims = arena.dims();
+
+    // follow the centroid of everyone still standing, or everyone if nobody is
+    let standing: Vec<&Player> = players.iter().filter(|p| !p.downed).collect();
+    let followed: Vec<&Player> = if standing.is_empty() { players.iter().collect() } else { standing };
+    let followed: Vec<(Vec2, Vec2)> = followed.iter().map(|p| (p.position, p.direction)).collect();
+    if let Some((target, target_height)) = frame_players(&followed, aspect, map_dims) {
+        rig.view_height = lerp(rig.view_height, target_height, 1.0 - (-ZOOM_SPEED * dt).exp());
+        rig.position = rig.position.lerp(target, 1.0 - (-FOLLOW_SPEED * dt).exp());
+    }
+
+    let half_view = vec2(rig.view_height * aspect, rig.view_height) / 2.0;
+    rig.position = clamp_to_map(rig.position, half_view, map_dims / 2.0);
+
+    let added = shakes.iter().map(|shake| shake.0).sum();
+    rig.trauma = next_trauma(rig.trauma, added, dt);
+
+    let shake = rig.trauma * rig.trauma;
+    let mut rng = rand::thread_rng();
+    let offset = shake * MAX_SHAKE_OFFSET * vec2(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+    let angle = shake * MAX_SHAKE_ANGLE * rng.gen_range(-1.0..1.0);
+
+    transform.translation = (rig.position + offset).extend(transform.translation.z);
+    transform.rotation = Quat::from_rotation_z(angle);
+
+    let scale = rig.view_height / VIEW_HEIGHT;
+    if (projection.scale - scale).abs() > 0.001 {
+        projection.scale = scale;
+    }
+}
+
+#[cfg(test)]
+fn test_camera(position: Vec2, scale: f32, angle: f32, window: Vec2) -> (GlobalTransform, Mat4) {
+    let mut projection = OrthographicProjection {
+        scaling_mode: ScalingMode::FixedVertical(VIEW_HEIGHT),
+        scale,
+        ..default()
+    };
+    projection.update(window.x, window.y);
+    let transform = Transform {
+        translation: position.extend(999.9),
+        rotation: Quat::from_rotation_z(angle),
+        ..default()
+    };
+    return (GlobalTransform::from(transform), projection.get_projection_matrix());
+}
+
+#[cfg(test)]
+fn assert_near(a: Vec2, b: Vec2) {
+    assert!((a - b).length() < 1e-2, "{:?} != {:?}", a, b);
+}
+
+#[test]
+fn test_viewport_to_world() {
+    let window = vec2(1600.0, 800.0);
+    let (transform, projection) = test_camera(Vec2::ZERO, 1.0, 0.0, window);
+
+    assert_near(viewport_to_world_2d(window / 2.0, window, &transform, projection).unwrap(), Vec2::ZERO);
+    assert_near(
+        viewport_to_world_2d(Vec2::ZERO, window, &transform, projection).unwrap(),
+        vec2(-800.0, -VIEW_HEIGHT / 2.0),
+    );
+    assert_near(
+        viewport_to_world_2d(window, window, &transform, projection).unwrap(),
+        vec2(800.0, VIEW_HEIGHT / 2.0),
+    );
+    assert!(viewport_to_world_2d(Vec2::ZERO, Vec2::ZERO, &transform, projection).is_none());
+}
+
+#[test]
+fn test_viewport_to_world_moved_and_zoomed() {
+    let window = vec2(800.0, 800.0);
+    let (transform, projection) = test_camera(vec2(300.0, -200.0), 2.0, 0.0, window);
+
+    assert_near(viewport_to_world_2d(window / 2.0, window, &transform, projection).unwrap(), vec2(300.0, -200.0));
+    assert_near(
+        viewport_to_world_2d(window, window, &transform, projection).unwrap(),
+        vec2(300.0, -200.0) + VIEW_HEIGHT * Vec2::ONE,
+    );
+}
+
+#[test]
+fn test_viewport_to_world_resized() {
+    // a fixed vertical projection shows the same world height at any window size or dpi
+    for window in [vec2(800.0, 600.0), vec2(1600.0, 1200.0), vec2(400.0, 300.0)] {
+        let (transform, projection) = test_camera(Vec2::ZERO, 1.0, 0.0, window);
+        assert_near(
+            viewport_to_world_2d(vec2(window.x / 2.0, window.y), window, &transform, projection).unwrap(),
+            vec2(0.0, VIEW_HEIGHT / 2.0),
+        );
+    }
+}
+
+#[test]
+fn test_world_to_viewport_round_trip() {
+    let window = vec2(1280.0, 720.0);
+    let (transform, projection) = test_camera(vec2(-50.0, 75.0), 1.5, 0.3, window);
+
+    for pos in [Vec2::ZERO, vec2(100.0, 30.0), vec2(-400.0, 250.0)] {
+        let viewport = world_to_viewport_2d(pos, window, &transform, projection).unwrap();
+        assert_near(viewport_to_world_2d(viewport, window, &transform, projection).unwrap(), pos);
+    }
+}
+
+#[test]
+fn test_cursor_picks_the_camera_on_top() {
+    use bevy::{
+        asset::AssetPlugin,
+        render::{camera::camera_system, camera::Viewport},
+        window::{WindowCreated, WindowId, WindowResized},
+    };
+
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin::default())
+        .add_asset::<Image>()
+        .add_event::<WindowCreated>()
+        .add_event::<WindowResized>()
+        .add_system(camera_system::<OrthographicProjection>);
+    let mut windows = Windows::default();
+    windows.add(Window::new(WindowId::primary(), &default(), 800, 600, 1.0, None, None));
+    app.insert_resource(windows);
+
+    let spawn_camera = |app: &mut App, priority, viewport, position: Vec2| {
+        let mut bundle = Camera2dBundle::default();
+        bundle.camera.priority = priority;
+        bundle.camera.viewport = viewport;
+        bundle.transform.translation = position.extend(999.9);
+        bundle.global_transform = GlobalTransform::from(bundle.transform);
+        return app.world.spawn(bundle).id();
+    };
+    spawn_camera(&mut app, 0, None, Vec2::ZERO);
+    // a 200x150 inset in the top right corner, looking somewhere else entirely
+    let inset = Viewport {
+        physical_position: UVec2::new(600, 0),
+        physical_size: UVec2::new(200, 150),
+        ..default()
+    };
+    let inset = spawn_camera(&mut app, 1, Some(inset), vec2(5000.0, 5000.0));
+    app.update();
+
+    let cursor = |app: &mut App, pos| {
+        let mut cameras = app.world.query::<(&Camera, &GlobalTransform)>();
+        let windows = app.world.resource::<Windows>();
+        return cursor_to_world(windows.get_primary().unwrap(), cameras.iter(&app.world), pos).unwrap();
+    };
+    // the middle of the inset, cursor positions start at the bottom left
+    assert_near(cursor(&mut app, vec2(700.0, 525.0)), vec2(5000.0, 5000.0));
+    assert_near(cursor(&mut app, vec2(400.0, 300.0)), Vec2::ZERO);
+
+    app.world.get_mut::<Camera>(inset).unwrap().is_active = false;
+    assert_near(cursor(&mut app, vec2(700.0, 525.0)), vec2(300.0, 225.0));
+}
+
+#[test]
+fn test_framing_zooms_out_to_fit_players() {
+    let map = vec2(10000.0, 10000.0);
+    let aspect = 16.0 / 9.0;
+
+    // one player alone gets the normal view, looking ahead of where they aim
+    let (target, height) = frame_players(&[(vec2(100.0, 50.0), Vec2::X)], aspect, map).unwrap();
+    assert_near(target, vec2(100.0 + LOOK_AHEAD, 50.0));
+    assert_eq!(height, VIEW_HEIGHT);
+
+    // two far apart on either side get the middle, zoomed out so both fit across the screen
+    let players = [(vec2(-2000.0, 0.0), Vec2::ZERO), (vec2(2000.0, 0.0), Vec2::ZERO)];
+    let (target, height) = frame_players(&players, aspect, map).unwrap();
+    assert_near(target, Vec2::ZERO);
+    assert!(height * aspect > 4000.0, "{}", height);
+
+    // but never further than the whole map
+    let (_, height) = frame_players(&players, aspect, vec2(5000.0, 1500.0)).unwrap();
+    assert_eq!(height, 1500.0);
+
+    assert!(frame_players(&[], aspect, map).is_none());
+}
+
+#[test]
+fn test_shake_trauma_decays_to_zero() {
+    let mut trauma = next_trauma(0.0, 0.7, 0.0);
+    assert_eq!(trauma, 0.7);
+    // piling on more hits never goes past full shake
+    trauma = next_trauma(trauma, 0.7, 0.0);
+    assert_eq!(trauma, 1.0);
+
+    let mut last = trauma;
+    for _ in 0..60 {
+        trauma = next_trauma(trauma, 0.0, 1.0 / 60.0);
+        assert!(trauma < last || trauma == 0.0);
+        last = trauma;
+    }
+    assert_eq!(trauma, 0.0);
+}
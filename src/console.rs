@@ -0,0 +1,421 @@
+use std::collections::BTreeMap;
+
+use bevy_egui::{egui, EguiContext};
+use strum::IntoEnumIterator;
+
+use crate::*;
+
+const MAX_LOG: usize = 200;
+// enemies spawned from the console appear this far from the first player
+const SPAWN_DISTANCE: f32 = 350.0;
+
+/// Runs a console command with everything typed after its name. `Ok` text goes to the log
+/// as is, `Err` as an error.
+pub type CommandFn = fn(&mut World, &[&str]) -> Result<String, String>;
+
+pub struct ConsoleCommand {
+    pub usage: String,
+    // offered by tab completion for the first argument
+    pub completions: Vec<String>,
+    pub run: CommandFn,
+}
+
+/// Developer switches that gameplay checks.
+#[derive(Resource, Default)]
+pub struct DevTools {
+    pub overlay: bool,
+    pub god: bool,
+    // rapier's collider outlines
+    pub colliders: bool,
+    // entity shown in the inspector, picked with a middle click
+    pub inspected: Option<Entity>,
+}
+
+#[derive(Resource)]
+pub struct Console {
+    pub open: bool,
+    input: String,
+    log: Vec<String>,
+    history: Vec<String>,
+    // how far back through the history the up arrow has gone
+    history_index: Option<usize>,
+    // lines submitted this frame, run with world access afterwards
+    pending: Vec<String>,
+    commands: BTreeMap<String, ConsoleCommand>,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        return Console {
+            open: false,
+            input: String::new(),
+            log: vec!["Type 'help' for a list of commands".into()],
+            history: vec![],
+            history_index: None,
+            pending: vec![],
+            commands: BTreeMap::new(),
+        };
+    }
+}
+
+impl Console {
+    /// Adds a command, replacing any already registered under `name`.
+    pub fn register(&mut self, name: &str, usage: &str, completions: Vec<String>, run: CommandFn) {
+        self.commands.insert(
+            name.to_string(),
+            ConsoleCommand {
+                usage: usage.to_string(),
+                completions,
+                run,
+            },
+        );
+    }
+
+    fn print(&mut self, line: String) {
+        self.log.push(line);
+        if self.log.len() > MAX_LOG {
+            self.log.remove(0);
+        }
+    }
+
+    /// Finishes the command name, or its first argument, as far as every match agrees.
+    pub fn complete(&self, input: &str) -> Option<String> {
+        let mut words: Vec<&str> = input.split_whitespace().collect();
+        if input.ends_with(' ') {
+            words.push("");
+        }
+        let (prefix, candidates): (String, Vec<&str>) = match words.as_slice() {
+            [word] => (String::new(), self.commands.keys().map(|k| k.as_str()).filter(|k| k.starts_with(word)).collect()),
+            [name, word] => match self.commands.get(*name) {
+                Some(command) => (
+                    format!("{} ", name),
+                    command.completions.iter().map(|c| c.as_str()).filter(|c| c.starts_with(word)).collect(),
+                ),
+                None => return None,
+            },
+            _ => return None,
+        };
+        let first = candidates.first()?;
+        let common = candidates.iter().fold(first.to_string(), |common, c| {
+            common.chars().zip(c.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a).collect()
+        });
+        // a single match gets a space after it, ready for the next word
+        let space = if candidates.len() == 1 { " " } else { "" };
+        return Some(format!("{}{}{}", prefix, common, space));
+    }
+}
+
+/// The dev overlay switches and the console, with the built-in commands. Other modules add
+/// their own commands with [`ConsoleAppExt::add_console_command`].
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DevTools>()
+            .init_resource::<Console>()
+            .add_system(toggle)
+            .add_system(draw_console.after(toggle))
+            .add_system_to_stage(CoreStage::PostUpdate, run_commands);
+        add_builtins(app);
+    }
+}
+
+/// Extends the app with console commands from anywhere.
+pub trait ConsoleAppExt {
+    fn add_console_command(&mut self, name: &str, usage: &str, completions: Vec<String>, run: CommandFn) -> &mut Self;
+}
+
+impl ConsoleAppExt for App {
+    fn add_console_command(&mut self, name: &str, usage: &str, completions: Vec<String>, run: CommandFn) -> &mut Self {
+        self.world.get_resource_or_insert_with(Console::default).register(name, usage, completions, run);
+        return self;
+    }
+}
+
+fn parse<T: std::str::FromStr>(arg: Option<&&str>, what: &str) -> Result<T, String> {
+    let arg = arg.ok_or(format!("missing {}", what))?;
+    return arg.parse().map_err(|_| format!("'{}' isn't a valid {}", arg, what));
+}
+
+fn add_builtins(app: &mut App) {
+    app.add_console_command("help", "help - list every command", vec![], |world, _| {
+        let console = world.resource::<Console>();
+        return Ok(console.commands.values().map(|c| c.usage.clone()).collect::<Vec<_>>().join("\n"));
+    });
+
+    app.add_console_command(
+        "spawn",
+        "spawn enemy <grunt|boss> <n> - telegraph n enemies around the first player",
+        vec!["enemy".into()],
+        |world, args| {
+            if args.first() != Some(&"enemy") {
+                return Err("usage: spawn enemy <grunt|boss> <n>".into());
+            }
+            let boss = match args.get(1).copied() {
+                Some("grunt") => false,
+                Some("boss") => true,
+                other => return Err(format!("unknown enemy {:?}, try grunt or boss", other.unwrap_or(""))),
+            };
+            let count: usize = match args.get(2) {
+                Some(_) => parse(args.get(2), "count")?,
+                None => 1,
+            };
+            let center = first_player_position(world)?;
+            let difficulty = world.get_resource::<Dungeon>().map(|d| d.difficulty()).unwrap_or(1.0);
+            for i in 0..count {
+                let angle = i as f32 / count as f32 * std::f32::consts::TAU;
+                let pos = center + vec2(angle.cos(), angle.sin()) * SPAWN_DISTANCE;
+                world.spawn(SpawnWarningBundle::new(pos, boss, difficulty));
+            }
+            return Ok(format!("spawning {} {}", count, if boss { "boss" } else { "grunt" }));
+        },
+    );
+
+    app.add_console_command(
+        "give",
+        "give <pickup> - give every player a pickup",
+        PickupKind::iter().map(|k| format!("{:?}", k).to_lowercase()).collect(),
+        |world, args| {
+            let name = args.first().ok_or("missing pickup name")?.to_lowercase();
+            let kind = PickupKind::iter()
+                .find(|k| format!("{:?}", k).to_lowercase() == name)
+                .ok_or(format!("no pickup called '{}'", name))?;
+            let pickup = Pickup { kind, price: 0 };
+            let tuning = world.resource::<Tuning>().pickups.clone();
+            for mut player in world.query::<&mut Player>().iter_mut(world) {
+                pickup.apply(&mut player, &tuning);
+            }
+            return Ok(format!("gave {:?}", pickup.kind));
+        },
+    );
+
+    app.add_console_command("god", "god - players can't be hurt", vec![], |world, _| {
+        let mut dev = world.resource_mut::<DevTools>();
+        dev.god = !dev.god;
+        return Ok(format!("god mode {}", if dev.god { "on" } else { "off" }));
+    });
+
+    app.add_console_command("colliders", "colliders - show collider outlines", vec![], |world, _| {
+        let mut dev = world.resource_mut::<DevTools>();
+        dev.colliders = !dev.colliders;
+        return Ok(format!("colliders {}", if dev.colliders { "shown" } else { "hidden" }));
+    });
+
+    app.add_console_command("wave", "wave <n> - jump the current room to wave n", vec![], |world, args| {
+        let wave: i32 = parse(args.first(), "wave number")?;
+        let mut dungeon = world.get_resource_mut::<Dungeon>().ok_or("no game running")?;
+        let index = if dungeon.endless { Some(0) } else { dungeon.active_room };
+        let index = index.ok_or("not in a fight")?;
+        dungeon.rooms[index].wave = wave.max(0);
+        return Ok(format!("wave set to {}", wave.max(0)));
+    });
+
+    app.add_console_command(
+        "setstat",
+        "setstat <stat> <value> - set a stat for every player",
+        Stats::NAMES.iter().map(|s| s.to_string()).collect(),
+        |world, args| {
+            let name = *args.first().ok_or("missing stat name")?;
+            let value: f32 = parse(args.get(1), "value")?;
+            if !Stats::NAMES.contains(&name) {
+                return Err(format!("no stat called '{}'", name));
+            }
+            for mut player in world.query::<&mut Player>().iter_mut(world) {
+                if let Some(stat) = player.stats.get_mut(name) {
+                    *stat = Stat {
+                        base: value,
+                        multiply: 1.0,
+                        add: 0.0,
+                    };
+                }
+            }
+            return Ok(format!("{} set to {}", name, value));
+        },
+    );
+
+    app.add_console_command("seed", "seed <n|random> - dungeon seed for the next new game", vec!["random".into()], |world, args| {
+        let seed = match args.first().copied() {
+            Some("random") => None,
+            _ => Some(parse(args.first(), "seed")?),
+        };
+        world.resource_mut::<Game>().seed = seed;
+        return match seed {
+            Some(seed) => Ok(format!("new games use seed {}", seed)),
+            None => Ok("new games use a random seed".into()),
+        };
+    });
+
+    app.add_console_command("timescale", "timescale <speed> - slow down or speed up the game", vec![], |world, args| {
+        let speed: f32 = parse(args.first(), "speed")?;
+        if !(speed > 0.0) {
+            return Err("speed has to be above 0".into());
+        }
+        world.resource_mut::<Time>().set_relative_speed(speed);
+        return Ok(format!("time scale {}", speed));
+    });
+
+    app.add_console_command("kill_all", "kill_all - kill every enemy, counting the kills", vec![], |world, _| {
+        let enemies: Vec<(Entity, f32)> = world.query::<(Entity, &Enemy)>().iter(world).map(|(e, enemy)| (e, enemy.health)).collect();
+        let mut damages = world.resource_mut::<Events<Damage>>();
+        for &(target, health) in enemies.iter() {
+            damages.send(Damage {
+                target,
+                amount: health,
+                kind: DamageKind::Contact,
+                source: None,
+                shot: None,
+                crit: false,
+                impulse: Vec2::ZERO,
+            });
+        }
+        return Ok(format!("killed {} enemies", enemies.len()));
+    });
+}
+
+fn first_player_position(world: &mut World) -> Result<Vec2, String> {
+    return world
+        .query::<&Player>()
+        .iter(world)
+        .min_by_key(|p| p.slot)
+        .map(|p| p.position)
+        .ok_or("no players".into());
+}
+
+/// F1 for the dev overlay, F2 for collider outlines, backtick for the console.
+pub fn toggle(keys: Res<Input<KeyCode>>, mut dev: ResMut<DevTools>, mut console: ResMut<Console>) {
+    if keys.just_pressed(KeyCode::F1) {
+        dev.overlay = !dev.overlay;
+    }
+    if keys.just_pressed(KeyCode::F2) {
+        dev.colliders = !dev.colliders;
+    }
+    if keys.just_pressed(KeyCode::Grave) {
+        console.open = !console.open;
+    }
+}
+
+pub fn draw_console(mut egui_context: ResMut<EguiContext>, mut console: ResMut<Console>) {
+    if !console.open {
+        return;
+    }
+    let console = console.as_mut();
+    egui::Window::new("Console")
+        .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -10.0])
+        .default_width(500.0)
+        .collapsible(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for line in console.log.iter() {
+                        ui.monospace(line);
+                    }
+                });
+
+            // the backtick that opened the console shouldn't end up in it
+            console.input.retain(|c| c != '`');
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut console.input)
+                    .font(egui::TextStyle::Monospace)
+                    .desired_width(f32::INFINITY)
+                    .lock_focus(true),
+            );
+            response.request_focus();
+
+            let input = ui.input();
+            if input.key_pressed(egui::Key::Tab) {
+                if let Some(completed) = console.complete(&console.input) {
+                    console.input = completed;
+                }
+            }
+            if input.key_pressed(egui::Key::ArrowUp) && !console.history.is_empty() {
+                let index = console.history_index.map(|i| i.saturating_sub(1)).unwrap_or(console.history.len() - 1);
+                console.history_index = Some(index);
+                console.input = console.history[index].clone();
+            }
+            if input.key_pressed(egui::Key::ArrowDown) {
+                match console.history_index {
+                    Some(i) if i + 1 < console.history.len() => {
+                        console.history_index = Some(i + 1);
+                        console.input = console.history[i + 1].clone();
+                    }
+                    _ => {
+                        console.history_index = None;
+                        console.input.clear();
+                    }
+                }
+            }
+            if input.key_pressed(egui::Key::Enter) {
+                let line = console.input.trim().to_string();
+                console.input.clear();
+                console.history_index = None;
+                if !line.is_empty() {
+                    if console.history.last() != Some(&line) {
+                        console.history.push(line.clone());
+                    }
+                    console.pending.push(line);
+                }
+            }
+        });
+}
+
+/// Runs whatever was entered this frame. Commands get the whole world to work with.
+pub fn run_commands(world: &mut World) {
+    let lines = std::mem::take(&mut world.resource_mut::<Console>().pending);
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let run = match world.resource::<Console>().commands.get(words[0]) {
+            Some(command) => Some(command.run),
+            None => None,
+        };
+        let output = match run {
+            Some(run) => run(world, &words[1..]),
+            None => Err(format!("unknown command '{}'", words[0])),
+        };
+        let mut console = world.resource_mut::<Console>();
+        console.print(format!("> {}", line));
+        match output {
+            Ok(text) if text.is_empty() => {}
+            Ok(text) => text.lines().for_each(|l| console.print(l.to_string())),
+            Err(e) => console.print(format!("error: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_app() -> App {
+    let mut app = App::new();
+    app.add_plugin(ConsolePlugin).init_resource::<Tuning>();
+    return app;
+}
+
+#[test]
+fn completes_commands_and_arguments() {
+    let app = test_app();
+    let console = app.world.resource::<Console>();
+    assert_eq!(console.complete("ki"), Some("kill_all ".into()));
+    assert_eq!(console.complete("setstat crit_"), Some("setstat crit_".into()));
+    assert_eq!(console.complete("setstat crit_c"), Some("setstat crit_chance ".into()));
+    assert_eq!(console.complete("give he"), Some("give heart ".into()));
+    assert_eq!(console.complete("nope"), None);
+}
+
+#[test]
+fn commands_run_against_the_world() {
+    let mut app = test_app();
+    app.add_console_command("echo", "echo <words> - say it back", vec![], |_, args| Ok(args.join(" ")));
+    let world = &mut app.world;
+    world.spawn(Player::default());
+
+    world.resource_mut::<Console>().pending =
+        vec!["god".into(), "setstat damage 200".into(), "echo hi there".into(), "bogus".into()];
+    run_commands(world);
+
+    assert!(world.resource::<DevTools>().god);
+    let damage = world.query::<&Player>().single(world).stats.damage.value();
+    assert_eq!(damage, 200.0);
+    let log = &world.resource::<Console>().log;
+    assert!(log.iter().any(|l| l == "hi there"));
+    assert!(log.iter().any(|l| l.starts_with("error: unknown command")));
+}
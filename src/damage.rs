@@ -0,0 +1,110 @@
+use rand::Rng;
+
+use crate::*;
+
+// chance an enemy drops a heart, growing with every wave fought on the floor
+const HEART_CHANCE: f32 = 0.03;
+const HEART_CHANCE_PER_WAVE: f32 = 0.01;
+const MAX_HEART_CHANCE: f32 = 0.15;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DamageKind {
+    Beam,
+    Bullet,
+    Contact,
+    Explosion,
+    Hazard,
+    Lightning,
+}
+
+/// Every bit of damage in the game goes through this event, whoever deals it and
+/// whatever it hits, so kills and score are only counted in one place.
+pub struct Damage {
+    pub target: Entity,
+    pub amount: f32,
+    pub kind: DamageKind,
+    // the player credited with a kill, if any
+    pub source: Option<Entity>,
+    // the player's shot this came from, so a crit that pierces is only counted once
+    pub shot: Option<u32>,
+    pub crit: bool,
+    // knockback, before the target's mass is taken into account
+    pub impulse: Vec2,
+}
+
+/// Damage that actually landed, sent by [`apply`] once shields, god mode and targets that
+/// are already dead have had their say. Feedback goes off this rather than [`Damage`].
+pub struct DamageDealt {
+    pub target: Entity,
+    // health taken off, so a shielded player or overkill shows what was really lost
+    pub amount: f32,
+    pub kind: DamageKind,
+    pub crit: bool,
+}
+
+pub fn apply(
+    mut commands: Commands,
+    mut game: ResMut<Game>,
+    dungeon: Res<Dungeon>,
+    dev: Res<DevTools>,
+    mut colors: ResMut<Assets<ColorMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut damages: EventReader<Damage>,
+    mut dealt: EventWriter<DamageDealt>,
+    mut particles: EventWriter<EmitParticles>,
+    mut sounds: EventWriter<PlaySound>,
+    mut enemies: Query<&mut Enemy>,
+    mut players: Query<&mut Player>,
+    mut props: Query<&mut Prop>,
+) {
+    for damage in damages.iter() {
+        let landed = |amount: f32| DamageDealt {
+            target: damage.target,
+            amount,
+            kind: damage.kind,
+            crit: damage.crit,
+        };
+        if let Ok(mut enemy) = enemies.get_mut(damage.target) {
+            // already dead, just not despawned yet
+            if enemy.health <= 0.0 {
+                continue;
+            }
+            dealt.send(landed(damage.amount.min(enemy.health)));
+            enemy.health -= damage.amount;
+            if damage.crit {
+                if let (Some(shot), Some(Ok(mut shooter))) = (damage.shot, damage.source.map(|e| players.get_mut(e))) {
+                    shooter.count_crit(shot);
+                }
+            }
+            enemy.knock_back(damage.impulse);
+            if enemy.health <= 0.0 {
+                game.kills += 1;
+                sounds.send(PlaySound::at(Sfx::EnemyDeath, enemy.position));
+                particles.send(EmitParticles {
+                    effect: "death",
+                    position: enemy.position,
+                    direction: Vec2::ZERO,
+                });
+                if let Some(Ok(mut shooter)) = damage.source.map(|e| players.get_mut(e)) {
+                    shooter.score += enemy.max_health;
+                }
+                let waves: i32 = dungeon.rooms.iter().map(|r| r.wave).sum();
+                let chance = (HEART_CHANCE + HEART_CHANCE_PER_WAVE * waves as f32).min(MAX_HEART_CHANCE);
+                if rand::thread_rng().gen::<f32>() < chance {
+                    commands.spawn(PickupBundle::from_kind(enemy.position, PickupKind::Heart, 0, &mut colors, &mut meshes));
+                }
+            }
+        } else if let Ok(mut player) = players.get_mut(damage.target) {
+            if !player.downed && !dev.god {
+                let lost = player.take_damage(damage.amount);
+                dealt.send(landed(lost));
+            }
+        } else if let Ok(mut prop) = props.get_mut(damage.target) {
+            if prop.health > 0.0 {
+                dealt.send(landed(damage.amount.min(prop.health)));
+                prop.health -= damage.amount;
+                prop.last_hit_by = damage.source;
+            }
+        }
+    }
+}
@@ -0,0 +1,506 @@
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::*;
+
+// rooms are laid out on a grid of slots, one room per slot at most
+const SLOTS: (i32, i32) = (3, 3);
+const SLOT_W: i32 = 20;
+const SLOT_H: i32 = 14;
+const CORRIDOR_WIDTH: i32 = 3;
+const TILE_SIZE: f32 = 50.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoomKind {
+    Start,
+    Combat,
+    Treasure,
+    Shop,
+    Boss,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoomState {
+    Unvisited,
+    Active,
+    Cleared,
+}
+
+#[derive(Clone, Debug)]
+pub struct Room {
+    pub kind: RoomKind,
+    pub state: RoomState,
+    // floor cells, in arena grid coordinates (row 0 at the top)
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+    // corridor cells right outside the room, walled off while it's active
+    pub doors: Vec<(i32, i32)>,
+    pub waves_left: i32,
+    pub wave: i32,
+    // number of rooms between this one and the start
+    pub depth: u32,
+}
+
+impl Room {
+    fn new(kind: RoomKind, x: i32, y: i32, w: i32, h: i32, depth: u32) -> Room {
+        return Room {
+            kind,
+            state: RoomState::Unvisited,
+            x,
+            y,
+            w,
+            h,
+            doors: Vec::new(),
+            waves_left: 0,
+            wave: 0,
+            depth,
+        };
+    }
+
+    /// `margin` shrinks the room, so a player standing in the doorway isn't counted as inside.
+    pub fn contains(&self, (x, y): (i32, i32), margin: i32) -> bool {
+        return x >= self.x + margin
+            && x < self.x + self.w - margin
+            && y >= self.y + margin
+            && y < self.y + self.h - margin;
+    }
+
+    pub fn center(&self, arena: &Arena) -> Vec2 {
+        let a = arena.cell_center(self.x, self.y);
+        let b = arena.cell_center(self.x + self.w - 1, self.y + self.h - 1);
+        return (a + b) / 2.0;
+    }
+
+    /// Where the way down appears once the boss room is cleared, a little above the reward.
+    pub fn stairs_cell(&self) -> (i32, i32) {
+        return (self.x + self.w / 2, self.y + self.h / 2 - 2);
+    }
+
+    pub fn random_position(&self, arena: &Arena) -> Vec2 {
+        let mut rng = rand::thread_rng();
+        let x = rng.gen_range(self.x + 1..self.x + self.w - 1);
+        let y = rng.gen_range(self.y + 1..self.y + self.h - 1);
+        return arena.cell_center(x, y);
+    }
+}
+
+#[derive(Resource)]
+pub struct Dungeon {
+    pub seed: u64,
+    pub floor: u32,
+    pub rooms: Vec<Room>,
+    pub active_room: Option<usize>,
+    // the old single arena with never ending waves
+    pub endless: bool,
+}
+
+impl Dungeon {
+    /// Multiplier for enemy stats and wave sizes, rising with each floor.
+    pub fn difficulty(&self) -> f32 {
+        return 1.0 + 0.25 * (self.floor - 1) as f32;
+    }
+
+    pub fn start_position(&self, arena: &Arena) -> Vec2 {
+        return self.rooms.first().map(|r| r.center(arena)).unwrap_or(Vec2::ZERO);
+    }
+
+    pub fn room_at(&self, cell: (i32, i32), margin: i32) -> Option<usize> {
+        return self.rooms.iter().position(|r| r.contains(cell, margin));
+    }
+
+    /// Wraps a hand made map as a single room that never runs out of waves.
+    pub fn endless(layout: &MapLayout) -> (Dungeon, Arena) {
+        let arena = Arena::from_layout(layout);
+        let room = Room::new(RoomKind::Combat, 0, 0, arena.width as i32, arena.height as i32, 0);
+        let dungeon = Dungeon {
+            seed: 0,
+            floor: 1,
+            rooms: vec![room],
+            active_room: None,
+            endless: true,
+        };
+        return (dungeon, arena);
+    }
+
+    /// Builds floor `floor` of the run. The same seed always gives the same floors.
+    pub fn generate(seed: u64, floor: u32) -> (Dungeon, Arena) {
+        let mut rng = StdRng::seed_from_u64(seed ^ (floor as u64).wrapping_mul(0x9E3779B97F4A7C15));
+        let room_count = (4 + floor as usize).min((SLOTS.0 * SLOTS.1) as usize);
+
+        // grow a tree of rooms over the slot grid, each new room next to an existing one
+        let mut slots: Vec<((i32, i32), u32)> = vec![((rng.gen_range(0..SLOTS.0), rng.gen_range(0..SLOTS.1)), 0)];
+        let mut links: Vec<(usize, usize)> = Vec::new();
+        while slots.len() < room_count {
+            let parent = rng.gen_range(0..slots.len());
+            let ((px, py), depth) = slots[parent];
+            let (dx, dy) = *[(1, 0), (-1, 0), (0, 1), (0, -1)].choose(&mut rng).unwrap();
+            let slot = (px + dx, py + dy);
+            if slot.0 < 0 || slot.1 < 0 || slot.0 >= SLOTS.0 || slot.1 >= SLOTS.1 {
+                continue;
+            }
+            if slots.iter().any(|(s, _)| *s == slot) {
+                continue;
+            }
+            slots.push((slot, depth + 1));
+            links.push((parent, slots.len() - 1));
+        }
+
+        let mut rooms: Vec<Room> = slots
+            .iter()
+            .map(|&((sx, sy), depth)| {
+                let w = rng.gen_range(10..=SLOT_W - 4);
+                let h = rng.gen_range(8..=SLOT_H - 4);
+                let x = sx * SLOT_W + (SLOT_W - w) / 2;
+                let y = sy * SLOT_H + (SLOT_H - h) / 2;
+                Room::new(RoomKind::Combat, x, y, w, h, depth)
+            })
+            .collect();
+
+        // the deepest room holds the boss, a couple of the others get loot
+        rooms[0].kind = RoomKind::Start;
+        let boss = (1..rooms.len()).max_by_key(|&i| rooms[i].depth).unwrap_or(0);
+        rooms[boss].kind = RoomKind::Boss;
+        let mut others: Vec<usize> = (1..rooms.len()).filter(|&i| i != boss).collect();
+        others.shuffle(&mut rng);
+        if let Some(&i) = others.get(0) {
+            rooms[i].kind = RoomKind::Treasure;
+        }
+        // leave at least one combat room besides the boss, even on the first floor
+        if others.len() >= 3 {
+            rooms[others[1]].kind = RoomKind::Shop;
+        }
+        for room in rooms.iter_mut() {
+            room.waves_left = match room.kind {
+                RoomKind::Combat => 1 + (floor as i32 / 2).min(2),
+                RoomKind::Boss => 1,
+                _ => 0,
+            };
+        }
+
+        let width = SLOTS.0 * SLOT_W;
+        let height = SLOTS.1 * SLOT_H;
+        let mut grid = vec![vec!['#'; width as usize]; height as usize];
+        for room in rooms.iter() {
+            for y in room.y..room.y + room.h {
+                for x in room.x..room.x + room.w {
+                    grid[y as usize][x as usize] = '.';
+                }
+            }
+        }
+
+        // something to fight around in the combat rooms: a few props and one patch of hazard
+        for room in rooms.iter().filter(|r| matches!(r.kind, RoomKind::Combat | RoomKind::Boss)) {
+            let random_cell = |rng: &mut StdRng, margin: i32| {
+                (
+                    rng.gen_range(room.x + margin..room.x + room.w - margin) as usize,
+                    rng.gen_range(room.y + margin..room.y + room.h - margin) as usize,
+                )
+            };
+            // nothing may cover the spot where the stairs will appear
+            let stairs = (room.kind == RoomKind::Boss).then(|| room.stairs_cell());
+            let is_clear = |cells: &[(usize, usize)]| cells.iter().all(|&(x, y)| Some((x as i32, y as i32)) != stairs);
+            let patch = loop {
+                let (px, py) = random_cell(&mut rng, 2);
+                let patch = [(px, py), (px + 1, py), (px, py + 1), (px + 1, py + 1)];
+                if is_clear(&patch) {
+                    break patch;
+                }
+            };
+            let hazard = *['^', '~', ','].choose(&mut rng).unwrap();
+            for (x, y) in patch {
+                grid[y][x] = hazard;
+            }
+            for _ in 0..rng.gen_range(1..=3) {
+                let (x, y) = random_cell(&mut rng, 1);
+                if is_clear(&[(x, y)]) {
+                    grid[y][x] = *['B', 'C', 'C'].choose(&mut rng).unwrap();
+                }
+            }
+        }
+
+        // straight corridors between the middles of linked slots, which every room covers
+        let slot_center = |(sx, sy): (i32, i32)| (sx * SLOT_W + SLOT_W / 2, sy * SLOT_H + SLOT_H / 2);
+        for &(a, b) in links.iter() {
+            let (ax, ay) = slot_center(slots[a].0);
+            let (bx, by) = slot_center(slots[b].0);
+            for x in ax.min(bx)..=ax.max(bx) {
+                for y in ay.min(by)..=ay.max(by) {
+                    for o in -(CORRIDOR_WIDTH / 2)..=CORRIDOR_WIDTH / 2 {
+                        let (cx, cy) = if ay == by { (x, y + o) } else { (x + o, y) };
+                        grid[cy as usize][cx as usize] = '.';
+                    }
+                }
+            }
+        }
+
+        // doors are the corridor cells touching a room's edge
+        for room in rooms.iter_mut() {
+            for y in room.y - 1..=room.y + room.h {
+                for x in room.x - 1..=room.x + room.w {
+                    let on_edge = (x == room.x - 1 || x == room.x + room.w) != (y == room.y - 1 || y == room.y + room.h);
+                    if on_edge && grid[y as usize][x as usize] == '.' {
+                        room.doors.push((x, y));
+                    }
+                }
+            }
+        }
+
+        let layout = MapLayout {
+            tile_size: TILE_SIZE,
+            rows: grid.into_iter().map(|row| row.into_iter().collect()).collect(),
+        };
+        let dungeon = Dungeon {
+            seed,
+            floor,
+            rooms,
+            active_room: None,
+            endless: false,
+        };
+        return (dungeon, Arena::from_layout(&layout));
+    }
+}
+
+/// Blocks a room's corridors while it's active.
+#[derive(Component, Default)]
+pub struct Door;
+
+/// Takes everyone down to the next floor.
+#[derive(Component, Default)]
+pub struct Stairs;
+
+#[derive(Bundle, Default)]
+pub struct StairsBundle {
+    stairs: Stairs,
+    collider: Collider,
+    sensor: Sensor,
+
+    #[bundle]
+    sprite: SpriteBundle,
+}
+
+impl StairsBundle {
+    pub fn new(pos: Vec2) -> Self {
+        return StairsBundle {
+            collider: Collider::cuboid(30.0, 30.0),
+            sprite: SpriteBundle {
+                sprite: Sprite {
+                    color: STAIRS_GLOW,
+                    custom_size: Some(vec2(60.0, 60.0)),
+                    ..default()
+                },
+                transform: Transform::from_translation(pos.extend(60.0)),
+                ..default()
+            },
+            ..default()
+        };
+    }
+}
+
+pub fn lock_doors(commands: &mut Commands, arena: &mut Arena, room: &Room) {
+    for &(x, y) in room.doors.iter() {
+        arena.set_tile(x, y, Tile::Wall);
+        commands.spawn((WallBundle::door(arena.cell_center(x, y), arena.tile_size), Door));
+    }
+}
+
+pub fn unlock_doors(commands: &mut Commands, arena: &mut Arena, room: &Room, doors: &Query<Entity, With<Door>>) {
+    for &(x, y) in room.doors.iter() {
+        arena.set_tile(x, y, Tile::Floor);
+    }
+    for door in doors.iter() {
+        commands.entity(door).despawn();
+    }
+}
+
+/// Everything that belongs to a floor rather than to the players. Parked bullets are left
+/// for their pool to hand out again.
+pub type FloorEntities = (Or<(
+    With<Map>,
+    With<Wall>,
+    With<Stairs>,
+    With<Pickup>,
+    With<Enemy>,
+    With<Bullet>,
+    With<Prop>,
+    With<Hazard>,
+    With<SpawnPoint>,
+    With<SpawnWarning>,
+)>, Without<Parked>);
+
+pub fn spawn_floor(
+    commands: &mut Commands,
+    dungeon: Dungeon,
+    arena: Arena,
+    map_tex: Handle<Image>,
+    old: &Query<Entity, FloorEntities>,
+) {
+    for e in old.iter() {
+        commands.entity(e).despawn();
+    }
+    spawn_arena(commands, &arena, map_tex);
+    commands.insert_resource(dungeon);
+    commands.insert_resource(arena);
+}
+
+pub fn tick_rooms(
+    mut commands: Commands,
+    mut dungeon: ResMut<Dungeon>,
+    mut arena: ResMut<Arena>,
+    mut colors: ResMut<Assets<ColorMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut players: Query<&mut Player>,
+) {
+    if dungeon.active_room.is_some() {
+        return;
+    }
+
+    let entered = players
+        .iter()
+        .filter(|p| !p.downed)
+        .find_map(|p| dungeon.room_at(arena.cell_at(p.position), 1).map(|i| (i, p.position)));
+    let (index, entry) = match entered {
+        Some((i, pos)) if dungeon.rooms[i].state == RoomState::Unvisited => (i, pos),
+        _ => return,
+    };
+
+    let floor = dungeon.floor;
+    let room = &mut dungeon.rooms[index];
+    match room.kind {
+        RoomKind::Combat | RoomKind::Boss => {
+            // pull everyone in so nobody gets locked out
+            for mut player in players.iter_mut() {
+                if !room.contains(arena.cell_at(player.position), 1) {
+                    player.position = entry;
+                    player.momentum = Vec2::ZERO;
+                }
+            }
+            room.state = RoomState::Active;
+            lock_doors(&mut commands, &mut arena, room);
+            dungeon.active_room = Some(index);
+        }
+        RoomKind::Treasure => {
+            room.state = RoomState::Cleared;
+            commands.spawn(PickupBundle::from_kind(room.center(&arena), PickupKind::random(), 0, &mut colors, &mut meshes));
+        }
+        RoomKind::Shop => {
+            room.state = RoomState::Cleared;
+            let center = room.center(&arena);
+            for offset in [-100.0, 0.0, 100.0] {
+                commands.spawn(PickupBundle::from_kind(
+                    center + vec2(offset, 0.0),
+                    PickupKind::random(),
+                    SHOP_PRICE * floor as i32,
+                    &mut colors,
+                    &mut meshes,
+                ));
+            }
+        }
+        RoomKind::Start => room.state = RoomState::Cleared,
+    }
+}
+
+pub fn tick_stairs(
+    mut commands: Commands,
+    game: Res<Game>,
+    dungeon: Res<Dungeon>,
+    stairs: Query<(&Transform, &Collider), With<Stairs>>,
+    mut players: Query<&mut Player>,
+    mut particles: EventWriter<EmitParticles>,
+    old: Query<Entity, FloorEntities>,
+    rapier_ctx: Res<RapierContext>,
+) {
+    for (transform, collider) in stairs.iter() {
+        let mut taken = false;
+        rapier_ctx.intersections_with_shape(
+            transform.translation.truncate(),
+            0.0,
+            collider,
+            QueryFilter::default(),
+            |entity| {
+                if let Ok(player) = players.get(entity) {
+                    taken = !player.downed;
+                }
+                !taken
+            },
+        );
+
+        if taken {
+            let (next, arena) = Dungeon::generate(dungeon.seed, dungeon.floor + 1);
+            let start = next.start_position(&arena);
+            for mut player in players.iter_mut() {
+                player.position = start;
+                player.momentum = Vec2::ZERO;
+            }
+            // a new floor is as close as the game gets to levelling up
+            particles.send(EmitParticles {
+                effect: "level_up",
+                position: start,
+                direction: Vec2::Y,
+            });
+            spawn_floor(&mut commands, next, arena, game.handles.map_tex.clone(), &old);
+            return;
+        }
+    }
+}
+
+#[test]
+fn test_generate_is_deterministic() {
+    for floor in 1..=5 {
+        let (a, arena_a) = Dungeon::generate(42, floor);
+        let (b, arena_b) = Dungeon::generate(42, floor);
+        let rooms = |d: &Dungeon| d.rooms.iter().map(|r| (r.kind, r.x, r.y, r.w, r.h, r.doors.clone())).collect::<Vec<_>>();
+        assert_eq!(rooms(&a), rooms(&b));
+        assert_eq!((arena_a.width, arena_a.height), (arena_b.width, arena_b.height));
+        for y in 0..arena_a.height as i32 {
+            for x in 0..arena_a.width as i32 {
+                assert_eq!(arena_a.tile(x, y), arena_b.tile(x, y), "floor {} cell ({}, {})", floor, x, y);
+            }
+        }
+    }
+    // and neighbouring floors of one run aren't copies of each other
+    let rooms = |floor| Dungeon::generate(42, floor).0.rooms.iter().map(|r| (r.x, r.y, r.w, r.h)).collect::<Vec<_>>();
+    assert_ne!(rooms(1), rooms(2));
+}
+
+#[test]
+fn test_every_room_is_reachable_from_the_start() {
+    for seed in 0..20 {
+        for floor in [1, 3, 6] {
+            let (dungeon, arena) = Dungeon::generate(seed, floor);
+            let start = arena.cell_at(dungeon.start_position(&arena));
+            assert!(arena.tile(start.0, start.1).is_walkable());
+            let field = FlowField::build(&arena, &[start]);
+            for (i, room) in dungeon.rooms.iter().enumerate() {
+                for y in room.y..room.y + room.h {
+                    for x in room.x..room.x + room.w {
+                        let pos = arena.cell_center(x, y);
+                        assert!(
+                            field.distance(&arena, pos).is_some(),
+                            "seed {} floor {}: room {} cell ({}, {}) is cut off",
+                            seed,
+                            floor,
+                            i,
+                            x,
+                            y
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_stairs_land_on_plain_floor() {
+    for seed in 0..50 {
+        for floor in 1..=4 {
+            let (dungeon, arena) = Dungeon::generate(seed, floor);
+            let boss = dungeon.rooms.iter().find(|r| r.kind == RoomKind::Boss).unwrap();
+            let (x, y) = boss.stairs_cell();
+            assert!(boss.contains((x, y), 1));
+            assert_eq!(arena.tile(x, y), Tile::Floor, "seed {} floor {}", seed, floor);
+            assert!(arena.props.iter().all(|&(_, cell)| cell != (x, y)), "seed {} floor {}", seed, floor);
+        }
+    }
+}
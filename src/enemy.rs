@@ -3,8 +3,12 @@ use std::time::Duration;
 use bevy::time::Stopwatch;
 
 use crate::*;
+use player::Player;
 
-const ENEMY_DIMS: Vec2 = vec2(70.0, 90.0);
+// how quickly knockback bleeds off, per second
+const KNOCKBACK_DECAY: f32 = 8.0;
+// seconds an enemy can't steer or attack after being knocked back
+const HIT_STUN: f32 = 0.15;
 
 #[derive(Component, Default)]
 pub struct Enemy {
@@ -20,13 +24,31 @@ pub struct Enemy {
     pub hit_interval: Duration,
     pub point_value: i32,
     pub speed: f32,
+    pub mass: f32,
+    pub boss: bool,
 
     pub hit_timer: Stopwatch,
+    // velocity from being hit, in units per second
+    pub knockback: Vec2,
+    pub stun: f32,
+}
+
+impl Enemy {
+    /// Pushes the enemy away and briefly stuns it, which also restarts its attack timer.
+    pub fn knock_back(&mut self, impulse: Vec2) {
+        if impulse == Vec2::ZERO {
+            return;
+        }
+        self.knockback += impulse / self.mass;
+        self.stun = HIT_STUN;
+        self.hit_timer.reset();
+    }
 }
 
 #[derive(Bundle, Default)]
 pub struct EnemyBundle {
     enemy: Enemy,
+    flash: HitFlash,
     collider: Collider,
     sensor: Sensor,
 
@@ -38,6 +60,7 @@ impl EnemyBundle {
     pub fn new(
         pos: Vec2,
         tex: Handle<TextureAtlas>,
+        tuning: &EnemyTuning,
     ) -> Self {
         return Self {
             enemy: Enemy {
@@ -50,13 +73,14 @@ impl EnemyBundle {
                 max_health: 100,
                 hit_interval: Duration::from_millis(300),
                 speed: 5.0,
+                mass: 1.0,
                 ..default()
             },
-            collider: Collider::capsule_y(ENEMY_DIMS.y / 5.0, ENEMY_DIMS.x / 4.0),
+            collider: Collider::capsule_y(tuning.dims.y / 5.0, tuning.dims.x / 4.0),
             sprite: SpriteSheetBundle {
                 texture_atlas: tex,
                 sprite: TextureAtlasSprite {
-                    custom_size: Some(ENEMY_DIMS),
+                    custom_size: Some(tuning.dims),
                     ..default()
                 },
                 ..default()
@@ -64,6 +88,30 @@ impl EnemyBundle {
             ..default()
         };
     }
+
+    /// A much bigger, tougher enemy guarding the way down.
+    pub fn boss(pos: Vec2, tex: Handle<TextureAtlas>, tuning: &EnemyTuning) -> Self {
+        let dims = tuning.dims * 2.0;
+        let mut bundle = EnemyBundle::new(pos, tex, tuning);
+        bundle.enemy.damage = 25.0;
+        bundle.enemy.health = 2000.0;
+        bundle.enemy.max_health = 2000;
+        bundle.enemy.point_value = 2000;
+        bundle.enemy.speed = 3.0;
+        bundle.enemy.mass = 6.0;
+        bundle.enemy.boss = true;
+        bundle.collider = Collider::capsule_y(dims.y / 5.0, dims.x / 4.0);
+        bundle.sprite.sprite.custom_size = Some(dims);
+        return bundle;
+    }
+
+    /// Scales health and damage up for deeper floors.
+    pub fn with_difficulty(mut self, difficulty: f32) -> Self {
+        self.enemy.health *= difficulty;
+        self.enemy.max_health = (self.enemy.max_health as f32 * difficulty) as i32;
+        self.enemy.damage *= difficulty;
+        return self;
+    }
 }
 
 pub fn tick(
@@ -76,9 +124,19 @@ pub fn tick(
         &Collider,
         &mut TextureAtlasSprite,
     )>,
-    mut game: ResMut<Game>,
+    players: Query<&Player>,
+    mut damages: EventWriter<Damage>,
+    mut shakes: EventWriter<ScreenShake>,
+    arena: Res<Arena>,
+    tuning: Res<Tuning>,
+    flow_field: Res<FlowField>,
     rapier_ctx: Res<RapierContext>,
 ) {
+    let targets: Vec<Vec2> = players
+        .iter()
+        .filter(|player| !player.downed)
+        .map(|player| player.position)
+        .collect();
 
     let mut enemy_push_away = Vec::new();
     for (entity, mut enemy, mut transform, collider, mut sprite) in enemies.iter() {
@@ -89,7 +147,7 @@ pub fn tick(
                 collider,
                 QueryFilter::default(),
                 |intersecting_entity| {
-                    if intersecting_entity != entity && intersecting_entity != game.player.id.unwrap() {
+                    if intersecting_entity != entity {
                         if let Ok(other) = enemies.get(intersecting_entity) {
                             let dir = (enemy.position - other.1.position).normalize();
                             push += 10.0 * dir;
@@ -109,17 +167,34 @@ pub fn tick(
         }
 
         enemy.hit_timer.tick(time.delta());
-
-        let player_dir = (game.player.position - enemy.position).normalize();
-        let player_dist = (game.player.position - enemy.position).length();
-        enemy.direction = (25.5 * enemy.direction
-            + (player_dist / 700.0) * 10.5 * rand_norm_vec2()
-            + (1.0 - player_dist / 700.0) * 5.25 * player_dir
-            + *enemy_push_away.get(i).expect("oob"))
-            .normalize();
-        let new_pos = enemy.position + enemy.direction * enemy.speed;
-
-        enemy.position = clamp_position(&new_pos);
+        let dt = time.delta_seconds();
+        enemy.stun = (enemy.stun - dt).max(0.0);
+
+        // chase whoever is closest, and just wander if everyone is down
+        let nearest = targets
+            .iter()
+            .min_by(|a, b| a.distance(enemy.position).total_cmp(&b.distance(enemy.position)));
+        let (player_dir, player_dist) = match nearest {
+            Some(&target) => ((target - enemy.position).normalize_or_zero(), (target - enemy.position).length()),
+            None => (Vec2::ZERO, tuning.enemy.aggro_range),
+        };
+        // route around walls with the shared flow field, and go straight for them once close
+        let player_dir = flow_field.direction(&arena, enemy.position).unwrap_or(player_dir);
+        let stunned = enemy.stun > 0.0;
+        let steering = &tuning.enemy.steering;
+        let far = player_dist / tuning.enemy.aggro_range;
+        if !stunned {
+            enemy.direction = (steering.momentum * enemy.direction
+                + far * steering.wander * rand_norm_vec2()
+                + (1.0 - far) * steering.chase * player_dir
+                + *enemy_push_away.get(i).expect("oob"))
+                .normalize();
+        }
+        let speed = if stunned { 0.0 } else { enemy.speed * arena.tile_at(enemy.position).speed_scale() };
+        let step = enemy.direction * speed + enemy.knockback * dt;
+        enemy.knockback *= (-KNOCKBACK_DECAY * dt).exp();
+        let new_pos = arena.clamp_position(&(enemy.position + step));
+        enemy.position = move_and_slide(&rapier_ctx, collider, enemy.position, new_pos);
 
         sprite.flip_x = enemy.direction.x < 0.0;
 
@@ -132,17 +207,28 @@ pub fn tick(
             ..default()
         };
 
-        if enemy.hit_timer.elapsed() > enemy.hit_interval {
+        if !stunned && enemy.hit_timer.elapsed() > enemy.hit_interval {
             rapier_ctx.intersections_with_shape(
                 transform.translation.truncate(),
                 0.0,
                 collider,
                 QueryFilter::default(),
                 |entity| {
-                    if entity == game.player.id.unwrap() {
-                        game.player.health -= enemy.damage;
-                        enemy.hit_timer.reset();
-                        return false;
+                    if let Ok(player) = players.get(entity) {
+                        if !player.downed {
+                            damages.send(Damage {
+                                target: entity,
+                                amount: enemy.damage,
+                                kind: DamageKind::Contact,
+                                source: None,
+                                shot: None,
+                                crit: false,
+                                impulse: Vec2::ZERO,
+                            });
+                            enemy.hit_timer.reset();
+                            shakes.send(ScreenShake(0.4));
+                            return false;
+                        }
                     }
                     return true;
                 },
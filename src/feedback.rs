@@ -0,0 +1,200 @@
+use bevy_egui::{egui, EguiContext, EguiSettings};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+use ui::{to_egui_color, world_to_ui};
+
+// numbers are recycled oldest first once the pool is full
+const MAX_DAMAGE_NUMBERS: usize = 64;
+const NUMBER_LIFETIME: f32 = 0.8;
+const NUMBER_RISE_SPEED: f32 = 80.0;
+const FLASH_TIME: f32 = 0.1;
+// tints above 1.0 so the flash shows up on white sprites too, and blooms a little
+const FLASH_COLOR: Color = Color::rgb(4.0, 4.0, 4.0);
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeedbackConfig {
+    pub damage_numbers: bool,
+    pub hit_flash: bool,
+}
+
+impl Default for FeedbackConfig {
+    fn default() -> Self {
+        FeedbackConfig {
+            damage_numbers: true,
+            hit_flash: true,
+        }
+    }
+}
+
+/// Seconds left on the white flash after being hit.
+#[derive(Component, Default)]
+pub struct HitFlash(f32);
+
+#[derive(Clone, Copy, Default)]
+struct DamageNumber {
+    position: Vec2,
+    velocity: Vec2,
+    amount: f32,
+    color: Color,
+    crit: bool,
+    age: f32,
+    alive: bool,
+}
+
+/// Fixed pool of floating damage numbers, drawn through egui at their world positions.
+#[derive(Resource)]
+pub struct DamageNumbers {
+    pool: Vec<DamageNumber>,
+    next: usize,
+}
+
+impl Default for DamageNumbers {
+    fn default() -> Self {
+        DamageNumbers {
+            pool: vec![DamageNumber::default(); MAX_DAMAGE_NUMBERS],
+            next: 0,
+        }
+    }
+}
+
+impl DamageNumbers {
+    fn spawn(&mut self, number: DamageNumber) {
+        self.pool[self.next] = number;
+        self.next = (self.next + 1) % self.pool.len();
+    }
+}
+
+fn number_color(damage: &DamageDealt, hit_player: bool) -> Color {
+    if hit_player {
+        return Color::RED;
+    }
+    if damage.crit {
+        return Color::rgb(1.0, 0.8, 0.1);
+    }
+    match damage.kind {
+        DamageKind::Bullet | DamageKind::Contact => Color::WHITE,
+        DamageKind::Beam => Color::rgb(0.6, 1.0, 1.0),
+        DamageKind::Explosion => Color::ORANGE,
+        DamageKind::Hazard => Color::rgb(0.7, 0.4, 1.0),
+        DamageKind::Lightning => Color::rgb(0.5, 0.8, 1.0),
+    }
+}
+
+/// Turns every bit of damage dealt into a number and a flash on whatever took it.
+pub fn on_damage(
+    settings: Res<Settings>,
+    mut numbers: ResMut<DamageNumbers>,
+    mut damages: EventReader<DamageDealt>,
+    mut flashes: Query<&mut HitFlash>,
+    transforms: Query<&GlobalTransform>,
+    players: Query<(), With<Player>>,
+) {
+    let mut rng = rand::thread_rng();
+    for damage in damages.iter() {
+        if settings.feedback.hit_flash {
+            if let Ok(mut flash) = flashes.get_mut(damage.target) {
+                flash.0 = FLASH_TIME;
+            }
+        }
+        // a hit the shield soaked up entirely still flashes, but has no number to show
+        if !settings.feedback.damage_numbers || damage.amount <= 0.0 {
+            continue;
+        }
+        if let Ok(transform) = transforms.get(damage.target) {
+            numbers.spawn(DamageNumber {
+                position: transform.translation().truncate(),
+                velocity: vec2(rng.gen_range(-30.0..30.0), NUMBER_RISE_SPEED),
+                amount: damage.amount,
+                color: number_color(damage, players.contains(damage.target)),
+                crit: damage.crit,
+                age: 0.0,
+                alive: true,
+            });
+        }
+    }
+}
+
+/// Tints enemies and players towards white while their flash lasts. Also sets the
+/// players' base colour, faded out while they're down.
+pub fn tick_flashes(
+    time: Res<Time>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut enemies: Query<(&mut HitFlash, &mut TextureAtlasSprite), With<Enemy>>,
+    mut players: Query<(&Player, &mut HitFlash, &Handle<ColorMaterial>), Without<Enemy>>,
+) {
+    let dt = time.delta_seconds();
+    let flash_tint = |base: Color, flash: f32| {
+        let t = (flash / FLASH_TIME).clamp(0.0, 1.0);
+        let [r, g, b, a] = base.as_rgba_f32();
+        let [fr, fg, fb, _] = FLASH_COLOR.as_rgba_f32();
+        Color::rgba(lerp(r, fr, t), lerp(g, fg, t), lerp(b, fb, t), a)
+    };
+
+    for (mut flash, mut sprite) in enemies.iter_mut() {
+        flash.0 = (flash.0 - dt).max(0.0);
+        sprite.color = flash_tint(Color::WHITE, flash.0);
+    }
+    for (player, mut flash, material) in players.iter_mut() {
+        flash.0 = (flash.0 - dt).max(0.0);
+        if let Some(material) = materials.get_mut(material) {
+            let mut base = player.color;
+            if player.downed {
+                base.set_a(0.3);
+            }
+            material.color = flash_tint(base, flash.0);
+        }
+    }
+}
+
+pub fn draw_numbers(
+    time: Res<Time>,
+    mut numbers: ResMut<DamageNumbers>,
+    mut egui_context: ResMut<EguiContext>,
+    egui_settings: Res<EguiSettings>,
+    windows: Res<Windows>,
+    cameras: Query<(&Camera, &GlobalTransform), With<CameraRig>>,
+) {
+    let dt = time.delta_seconds();
+    for number in numbers.pool.iter_mut().filter(|n| n.alive) {
+        number.age += dt;
+        number.position += number.velocity * dt;
+        number.alive = number.age < NUMBER_LIFETIME;
+    }
+
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let (camera, camera_transform) = match cameras.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let ui_scale = egui_settings.scale_factor as f32;
+    let painter = egui_context
+        .ctx_mut()
+        .layer_painter(egui::LayerId::new(egui::Order::Background, egui::Id::new("damage numbers")));
+
+    for number in numbers.pool.iter().filter(|n| n.alive) {
+        let pos = match world_to_ui(window, camera, camera_transform, ui_scale, number.position) {
+            Some(pos) => pos,
+            None => continue,
+        };
+        let mut color = number.color;
+        color.set_a(1.0 - number.age / NUMBER_LIFETIME);
+        let (text, size) = if number.crit {
+            (format!("{:.0}!", number.amount), 14.0)
+        } else {
+            (format!("{:.0}", number.amount), 10.0)
+        };
+        painter.text(
+            pos,
+            egui::Align2::CENTER_CENTER,
+            text,
+            egui::FontId::proportional(size),
+            to_egui_color(color),
+        );
+    }
+}
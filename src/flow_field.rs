@@ -0,0 +1,257 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use crate::*;
+
+// step costs, roughly 1 : sqrt(2)
+const STRAIGHT_COST: u32 = 2;
+const DIAGONAL_COST: u32 = 3;
+const NEIGHBOURS: [(i32, i32); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+/// Walking distance from every floor cell to the nearest player, plus the direction to
+/// head in from each cell. Updated once per change and shared by every enemy, so steering
+/// is a single lookup no matter how many of them there are.
+#[derive(Resource, Default)]
+pub struct FlowField {
+    width: usize,
+    height: usize,
+    targets: Vec<(i32, i32)>,
+    costs: Vec<u32>,
+    // the target each cell's cost leads to, so a target moving only dirties its own cells
+    owners: Vec<Option<(i32, i32)>>,
+    directions: Vec<Vec2>,
+}
+
+impl FlowField {
+    pub fn build(arena: &Arena, targets: &[(i32, i32)]) -> FlowField {
+        let mut field = FlowField {
+            width: arena.width,
+            height: arena.height,
+            targets: vec![],
+            costs: vec![u32::MAX; arena.width * arena.height],
+            owners: vec![None; arena.width * arena.height],
+            directions: vec![Vec2::ZERO; arena.width * arena.height],
+        };
+        field.retarget(arena, targets);
+        return field;
+    }
+
+    /// Moves the field over to a new set of targets without starting from scratch. Cells
+    /// closest to a target that moved or went away are cleared and refilled from the cells
+    /// around them, and a new target only lowers the cells it's now closer to. Everything
+    /// else is left alone, so in co-op one player stepping into a new cell touches only the
+    /// part of the map nearest them. A lone player owns the whole map, so for them each step
+    /// still costs about as much as a full rebuild.
+    pub fn retarget(&mut self, arena: &Arena, targets: &[(i32, i32)]) {
+        let removed: Vec<(i32, i32)> = self.targets.iter().filter(|t| !targets.contains(t)).cloned().collect();
+        let mut open = BinaryHeap::new();
+        let mut changed = vec![];
+
+        // forget every cost that led to a target that's gone
+        for i in 0..self.costs.len() {
+            if matches!(self.owners[i], Some(owner) if removed.contains(&owner)) {
+                self.costs[i] = u32::MAX;
+                self.owners[i] = None;
+                changed.push(i);
+            }
+        }
+        // and let the still valid cells around the hole flow back into it
+        for &i in changed.iter() {
+            for (next, _) in walkable_neighbours(arena, self.cell(i)) {
+                let cost = self.costs[self.index(next).unwrap()];
+                if cost != u32::MAX {
+                    open.push(Reverse((cost, next)));
+                }
+            }
+        }
+        for &cell in targets.iter().filter(|t| !self.targets.contains(t)) {
+            if let Some(i) = self.index(cell) {
+                self.costs[i] = 0;
+                self.owners[i] = Some(cell);
+                changed.push(i);
+                open.push(Reverse((0, cell)));
+            }
+        }
+        self.targets = targets.to_vec();
+
+        // dijkstra outwards from all of that at once
+        while let Some(Reverse((cost, cell))) = open.pop() {
+            let i = self.index(cell).unwrap();
+            if cost > self.costs[i] {
+                continue;
+            }
+            for (next, step) in walkable_neighbours(arena, cell) {
+                let j = self.index(next).unwrap();
+                if cost + step < self.costs[j] {
+                    self.costs[j] = cost + step;
+                    self.owners[j] = self.owners[i];
+                    changed.push(j);
+                    open.push(Reverse((cost + step, next)));
+                }
+            }
+        }
+
+        // a cell's direction only depends on its neighbours' costs, so only re-point those
+        let mut dirty = vec![false; self.costs.len()];
+        for &i in changed.iter() {
+            dirty[i] = true;
+            for (next, _) in walkable_neighbours(arena, self.cell(i)) {
+                dirty[self.index(next).unwrap()] = true;
+            }
+        }
+        for i in (0..dirty.len()).filter(|&i| dirty[i]) {
+            self.point(arena, i);
+        }
+    }
+
+    // points a reachable cell at its cheapest neighbour
+    fn point(&mut self, arena: &Arena, i: usize) {
+        self.directions[i] = Vec2::ZERO;
+        if self.costs[i] == 0 || self.costs[i] == u32::MAX {
+            return;
+        }
+        let (x, y) = self.cell(i);
+        let best = walkable_neighbours(arena, (x, y)).min_by_key(|&(next, _)| self.costs[self.index(next).unwrap()]);
+        if let Some((next, _)) = best {
+            self.directions[i] = (arena.cell_center(next.0, next.1) - arena.cell_center(x, y)).normalize();
+        }
+    }
+
+    fn cell(&self, i: usize) -> (i32, i32) {
+        return ((i % self.width) as i32, (i / self.width) as i32);
+    }
+
+    fn index(&self, (x, y): (i32, i32)) -> Option<usize> {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+        return Some(y as usize * self.width + x as usize);
+    }
+
+    /// Which way to walk from `pos` to get closer to a player. `None` when already in a
+    /// player's cell or when no player can be reached from there.
+    pub fn direction(&self, arena: &Arena, pos: Vec2) -> Option<Vec2> {
+        let i = self.index(arena.cell_at(pos))?;
+        let dir = self.directions[i];
+        return (dir != Vec2::ZERO).then_some(dir);
+    }
+
+    /// Approximate walking distance from `pos` to the nearest player, in world units.
+    pub fn distance(&self, arena: &Arena, pos: Vec2) -> Option<f32> {
+        let i = self.index(arena.cell_at(pos))?;
+        let cost = self.costs[i];
+        return (cost != u32::MAX).then_some(cost as f32 / STRAIGHT_COST as f32 * arena.tile_size);
+    }
+}
+
+/// Walkable cells around `cell` and the cost of stepping there. Diagonals can't cut wall corners.
+fn walkable_neighbours(arena: &Arena, (x, y): (i32, i32)) -> impl Iterator<Item = ((i32, i32), u32)> + '_ {
+    NEIGHBOURS.iter().filter_map(move |&(dx, dy)| {
+        let walkable = |x: i32, y: i32| arena.tile(x, y).is_walkable();
+        if !walkable(x + dx, y + dy) {
+            return None;
+        }
+        if dx != 0 && dy != 0 {
+            if !walkable(x + dx, y) || !walkable(x, y + dy) {
+                return None;
+            }
+            return Some(((x + dx, y + dy), DIAGONAL_COST));
+        }
+        return Some(((x + dx, y + dy), STRAIGHT_COST));
+    })
+}
+
+/// Updates the field when a player steps into a new cell, and rebuilds it when the walls change.
+pub fn tick(mut field: ResMut<FlowField>, arena: Res<Arena>, players: Query<&Player>) {
+    let mut targets: Vec<(i32, i32)> = players
+        .iter()
+        .filter(|p| !p.downed)
+        .map(|p| arena.cell_at(p.position))
+        .filter(|&(x, y)| arena.tile(x, y).is_walkable())
+        .collect();
+    targets.sort();
+    targets.dedup();
+
+    if arena.is_changed() {
+        *field = FlowField::build(&arena, &targets);
+    } else if targets != field.targets {
+        field.retarget(&arena, &targets);
+    }
+}
+
+#[cfg(test)]
+fn test_arena(rows: &[&str]) -> Arena {
+    return Arena::from_layout(&MapLayout {
+        tile_size: 10.0,
+        rows: rows.iter().map(|r| r.to_string()).collect(),
+    });
+}
+
+#[test]
+fn test_flow_field_routes_around_walls() {
+    let arena = test_arena(&[
+        "#######", //
+        "#..#..#",
+        "#..#..#",
+        "#.....#",
+        "#######",
+    ]);
+    let field = FlowField::build(&arena, &[(5, 1)]);
+
+    // straight at the target is a wall, so head down towards the gap first
+    let dir = field.direction(&arena, arena.cell_center(1, 1)).unwrap();
+    assert!(dir.y < 0.0, "{:?}", dir);
+    // and at the gap, go right
+    let dir = field.direction(&arena, arena.cell_center(3, 3)).unwrap();
+    assert!(dir.x > 0.0, "{:?}", dir);
+
+    assert!(field.direction(&arena, arena.cell_center(5, 1)).is_none());
+    assert_eq!(field.distance(&arena, arena.cell_center(5, 1)), Some(0.0));
+    assert!(field.distance(&arena, arena.cell_center(1, 1)).unwrap() > 40.0);
+}
+
+#[test]
+fn test_flow_field_unreachable() {
+    let arena = test_arena(&[
+        "#####", //
+        "#.#.#",
+        "#####",
+    ]);
+    let field = FlowField::build(&arena, &[(1, 1)]);
+
+    assert!(field.direction(&arena, arena.cell_center(3, 1)).is_none());
+    assert!(field.distance(&arena, arena.cell_center(3, 1)).is_none());
+    assert!(field.distance(&arena, vec2(1000.0, 1000.0)).is_none());
+}
+
+#[test]
+fn test_flow_field_retarget_matches_rebuild() {
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    let arena = test_arena(&[
+        "##########", //
+        "#....#...#",
+        "#.##.#.#.#",
+        "#.#......#",
+        "#...##.#.#",
+        "#.#....#.#",
+        "##########",
+    ]);
+    let floor: Vec<(i32, i32)> = (0..arena.height as i32)
+        .flat_map(|y| (0..arena.width as i32).map(move |x| (x, y)))
+        .filter(|&(x, y)| arena.tile(x, y).is_walkable())
+        .collect();
+
+    // players wander, join and drop out, and the field should always match a fresh build
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut field = FlowField::build(&arena, &[(1, 1)]);
+    for _ in 0..50 {
+        let count = rng.gen_range(0..=3);
+        let mut targets: Vec<(i32, i32)> = floor.choose_multiple(&mut rng, count).cloned().collect();
+        targets.sort();
+        field.retarget(&arena, &targets);
+
+        let fresh = FlowField::build(&arena, &targets);
+        assert_eq!(field.costs, fresh.costs, "{:?}", targets);
+        assert_eq!(field.directions, fresh.directions, "{:?}", targets);
+    }
+}
@@ -1,38 +1,233 @@
+use rand::seq::SliceRandom;
+
 use crate::*;
-use rand::Rng;
-use strum::{IntoEnumIterator, EnumCount};
+
+// how long the warning marker shows before an enemy appears
+const TELEGRAPH_TIME: f32 = 1.0;
+// enemies never appear closer than this to a standing player, if there's any room for it
+const MIN_SPAWN_DISTANCE: f32 = 300.0;
+const SPAWN_ATTEMPTS: usize = 20;
+
+/// A pulsing marker where an enemy is about to appear.
+#[derive(Component)]
+pub struct SpawnWarning {
+    timer: Timer,
+    boss: bool,
+    difficulty: f32,
+}
+
+#[derive(Bundle)]
+pub struct SpawnWarningBundle {
+    warning: SpawnWarning,
+
+    #[bundle]
+    sprite: SpriteBundle,
+}
+
+impl SpawnWarningBundle {
+    pub fn new(pos: Vec2, boss: bool, difficulty: f32) -> Self {
+        let size = if boss { 140.0 } else { 70.0 };
+        return SpawnWarningBundle {
+            warning: SpawnWarning {
+                timer: Timer::from_seconds(TELEGRAPH_TIME, TimerMode::Once),
+                boss,
+                difficulty,
+            },
+            sprite: SpriteBundle {
+                sprite: Sprite {
+                    color: SPAWN_WARNING_GLOW,
+                    custom_size: Some(Vec2::splat(size)),
+                    ..default()
+                },
+                transform: Transform::from_translation(pos.extend(25.0)),
+                ..default()
+            },
+        };
+    }
+}
+
+/// Picks where in `room` the next enemy shows up: a spawn point from the map if the room has
+/// any, otherwise a random walkable spot, preferring ones well away from every player.
+fn spawn_position(arena: &Arena, room: &Room, players: &[Vec2]) -> Vec2 {
+    let nearest_player = |pos: &Vec2| players.iter().map(|p| p.distance(*pos)).fold(f32::INFINITY, f32::min);
+
+    let points: Vec<Vec2> = arena
+        .spawn_points
+        .iter()
+        .filter(|&&cell| room.contains(cell, 0))
+        .map(|&(x, y)| arena.cell_center(x, y))
+        .collect();
+    let candidates: Vec<Vec2> = if !points.is_empty() {
+        points
+    } else {
+        (0..SPAWN_ATTEMPTS)
+            .map(|_| room.random_position(arena))
+            .filter(|&pos| arena.is_walkable(pos))
+            .collect()
+    };
+
+    let safe: Vec<Vec2> = candidates
+        .iter()
+        .copied()
+        .filter(|pos| nearest_player(pos) >= MIN_SPAWN_DISTANCE)
+        .collect();
+    if let Some(&pos) = safe.choose(&mut rand::thread_rng()) {
+        return pos;
+    }
+    // nowhere is far enough away, so take whatever is furthest from everyone
+    return candidates
+        .into_iter()
+        .max_by(|a, b| nearest_player(a).total_cmp(&nearest_player(b)))
+        .unwrap_or(room.center(arena));
+}
 
 pub fn spawn_waves(
     mut commands: Commands,
-    mut game: ResMut<Game>,
-    colors: ResMut<Assets<ColorMaterial>>,
-    meshes: ResMut<Assets<Mesh>>,
+    mut dungeon: ResMut<Dungeon>,
+    mut arena: ResMut<Arena>,
+    mut colors: ResMut<Assets<ColorMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
     enemies: Query<(Entity, With<Enemy>)>,
-    pickups: Query<(Entity, (With<Pickup>, Without<Enemy>))>,
+    warnings: Query<(), With<SpawnWarning>>,
+    pickups: Query<&Pickup>,
+    players: Query<&Player>,
+    doors: Query<Entity, With<Door>>,
 ) {
-    if enemies.is_empty() && pickups.is_empty() {
-        if game.wave % 2 == 0 {
-            let r = rand::thread_rng().gen_range(0..PickupKind::COUNT);
-            let mut i = 0;
-            let mut kind = PickupKind::default();
-            for k in PickupKind::iter() {
-                if i == r {
-                    kind = k;
-                    break;
+    let index = match dungeon.active_room {
+        Some(index) => index,
+        None => return,
+    };
+    if !enemies.is_empty() || !warnings.is_empty() {
+        return;
+    }
+
+    let standing: Vec<Vec2> = players.iter().filter(|p| !p.downed).map(|p| p.position).collect();
+    let difficulty = dungeon.difficulty();
+    let endless = dungeon.endless;
+    let room = &mut dungeon.rooms[index];
+    let center = room.center(&arena);
+
+    if endless {
+        // the old arena: alternate between a pickup and an ever bigger wave.
+        // hearts lying around don't hold up the next wave
+        if !pickups.iter().any(|p| p.kind.is_loot()) {
+            if room.wave % 2 == 0 {
+                commands.spawn(PickupBundle::from_kind(center, PickupKind::random(), 0, &mut colors, &mut meshes));
+            } else {
+                for _ in 0..(room.wave + 5) {
+                    commands.spawn(SpawnWarningBundle::new(spawn_position(&arena, room, &standing), false, 1.0));
                 }
-                i += 1;
-            };
-            commands.spawn(PickupBundle::from_kind(
-                Vec2::ZERO, kind, colors, meshes
-            ));
-        } else {
-            for _ in 0..(game.wave + 5) {
-                commands.spawn(EnemyBundle::new(
-                    rand_norm_vec2() * MAP_DIMS / 2.0,
-                    game.handles.enemy_atlas.clone(),
-                ));
             }
+            room.wave += 1;
+        }
+        return;
+    }
+
+    if room.waves_left > 0 {
+        if room.kind == RoomKind::Boss {
+            commands.spawn(SpawnWarningBundle::new(center, true, difficulty));
+        }
+        let count = ((3 + 2 * room.wave) as f32 * difficulty) as i32;
+        for _ in 0..count {
+            commands.spawn(SpawnWarningBundle::new(spawn_position(&arena, room, &standing), false, difficulty));
         }
-        game.wave += 1;
+        room.waves_left -= 1;
+        room.wave += 1;
+        return;
+    }
+
+    // cleared: open up, hand out a reward, and show the way down after the boss
+    room.state = RoomState::Cleared;
+    unlock_doors(&mut commands, &mut arena, room, &doors);
+    commands.spawn(PickupBundle::from_kind(center, PickupKind::random(), 0, &mut colors, &mut meshes));
+    if room.kind == RoomKind::Boss {
+        let (x, y) = room.stairs_cell();
+        commands.spawn(StairsBundle::new(arena.cell_center(x, y)));
+    }
+    dungeon.active_room = None;
+}
+
+/// Pulses the spawn markers and swaps them for enemies once their time is up.
+pub fn tick_spawn_warnings(
+    mut commands: Commands,
+    time: Res<Time>,
+    game: Res<Game>,
+    tuning: Res<Tuning>,
+    mut warnings: Query<(Entity, &mut SpawnWarning, &mut Sprite, &mut Transform)>,
+) {
+    for (entity, mut warning, mut sprite, mut transform) in warnings.iter_mut() {
+        warning.timer.tick(time.delta());
+        let t = warning.timer.percent();
+        // pulse faster as the spawn gets closer
+        let pulse = ((t * t * 40.0).sin() + 1.0) / 2.0;
+        sprite.color.set_a(0.2 + 0.6 * pulse);
+        transform.scale = Vec3::splat(0.5 + 0.5 * t);
+
+        if warning.timer.finished() {
+            commands.entity(entity).despawn();
+            let pos = transform.translation.truncate();
+            let enemy = if warning.boss {
+                EnemyBundle::boss(pos, game.handles.enemy_atlas.clone(), &tuning.enemy)
+            } else {
+                EnemyBundle::new(pos, game.handles.enemy_atlas.clone(), &tuning.enemy)
+            };
+            commands.spawn(enemy.with_difficulty(warning.difficulty));
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_room(rows: &[&str], (x, y, w, h): (i32, i32, i32, i32)) -> (Arena, Room) {
+    let (dungeon, arena) = Dungeon::endless(&MapLayout {
+        tile_size: 100.0,
+        rows: rows.iter().map(|r| r.to_string()).collect(),
+    });
+    let mut room = dungeon.rooms[0].clone();
+    (room.x, room.y, room.w, room.h) = (x, y, w, h);
+    return (arena, room);
+}
+
+#[test]
+fn test_spawn_position_keeps_away_from_players() {
+    let (arena, room) = test_room(
+        &[
+            "##############", //
+            "#............#",
+            "#..##...#....#",
+            "#..##...#....#",
+            "#......###...#",
+            "#............#",
+            "##############",
+        ],
+        // the right hand edge of the map is outside the room
+        (0, 0, 11, 7),
+    );
+    let players = [arena.cell_center(1, 1), arena.cell_center(1, 5)];
+    for _ in 0..200 {
+        let pos = spawn_position(&arena, &room, &players);
+        assert!(arena.is_walkable(pos), "{:?}", pos);
+        assert!(room.contains(arena.cell_at(pos), 0), "{:?}", pos);
+        for player in players.iter() {
+            assert!(player.distance(pos) >= MIN_SPAWN_DISTANCE, "{:?}", pos);
+        }
+    }
+}
+
+#[test]
+fn test_spawn_position_falls_back_to_furthest_spot() {
+    let (arena, room) = test_room(
+        &[
+            "#####", //
+            "#...#",
+            "#####",
+        ],
+        (0, 0, 5, 3),
+    );
+    // nowhere in the room is far enough away, so either end is the best there is
+    let player = arena.cell_center(2, 1);
+    for _ in 0..20 {
+        let pos = spawn_position(&arena, &room, &[player]);
+        assert!(arena.is_walkable(pos), "{:?}", pos);
+        assert!((player.distance(pos) - 100.0).abs() < 0.01, "{:?}", pos);
     }
 }
@@ -0,0 +1,236 @@
+use std::{
+    ops::RangeInclusive,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    core_pipeline::bloom::BloomSettings,
+    window::{PresentMode, WindowMode},
+};
+use bevy_egui::EguiSettings;
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+pub const RESOLUTIONS: [(f32, f32); 5] = [
+    (1280.0, 720.0),
+    (1366.0, 768.0),
+    (1600.0, 900.0),
+    (1920.0, 1080.0),
+    (2560.0, 1440.0),
+];
+// the only sample counts every backend supports
+pub const MSAA_SAMPLES: [u32; 2] = [1, 4];
+pub const UI_SCALE_RANGE: RangeInclusive<f32> = 1.0..=3.0;
+
+// colours brighter than 1.0, which the HDR camera keeps and the bloom spreads into a glow
+pub const LAVA_GLOW: Color = Color::rgb(2.0, 0.5, 0.1);
+pub const STAIRS_GLOW: Color = Color::rgb(0.4, 1.5, 2.0);
+pub const SPAWN_WARNING_GLOW: Color = Color::rgba(2.0, 0.2, 0.2, 0.5);
+pub const LIGHTNING_GLOW: Color = Color::rgb(1.5, 2.5, 4.0);
+pub const BEAM_GLOW: Color = Color::rgb(1.0, 3.0, 4.0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BloomPreset {
+    Off,
+    Low,
+    High,
+    Custom,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct BloomConfig {
+    pub threshold: f32,
+    pub knee: f32,
+    pub intensity: f32,
+    pub scale: f32,
+}
+
+impl BloomConfig {
+    fn to_settings(&self) -> BloomSettings {
+        return BloomSettings {
+            threshold: self.threshold,
+            knee: self.knee,
+            intensity: self.intensity,
+            scale: self.scale,
+        };
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphicsConfig {
+    pub bloom: BloomPreset,
+    // only used with the custom preset
+    pub custom_bloom: BloomConfig,
+    pub msaa: u32,
+    pub vsync: bool,
+    pub fullscreen: bool,
+    // window size when not fullscreen
+    pub resolution: (f32, f32),
+    pub ui_scale: f32,
+    // frames per second, 0 for no cap
+    pub fps_cap: u32,
+}
+
+impl Default for GraphicsConfig {
+    fn default() -> Self {
+        GraphicsConfig {
+            bloom: BloomPreset::Low,
+            custom_bloom: BloomPreset::Low.values().unwrap(),
+            msaa: 1,
+            vsync: true,
+            fullscreen: false,
+            resolution: (1280.0, 720.0),
+            ui_scale: 2.0,
+            fps_cap: 0,
+        }
+    }
+}
+
+impl BloomPreset {
+    /// Bloom for the presets; none for off, and the custom preset brings its own.
+    pub fn values(&self) -> Option<BloomConfig> {
+        match self {
+            BloomPreset::Off | BloomPreset::Custom => None,
+            // what the game always used to have
+            BloomPreset::Low => Some(BloomConfig {
+                threshold: 0.6,
+                knee: 0.1,
+                intensity: 0.3,
+                scale: 1.0,
+            }),
+            BloomPreset::High => Some(BloomConfig {
+                threshold: 0.5,
+                knee: 0.2,
+                intensity: 0.6,
+                scale: 1.2,
+            }),
+        }
+    }
+}
+
+impl GraphicsConfig {
+    /// Pulls hand edited values back to ones the menu could have picked.
+    pub fn sanitize(&mut self) {
+        self.msaa = *MSAA_SAMPLES.iter().min_by_key(|&&s| s.abs_diff(self.msaa)).unwrap();
+        self.ui_scale = if self.ui_scale.is_finite() {
+            self.ui_scale.clamp(*UI_SCALE_RANGE.start(), *UI_SCALE_RANGE.end())
+        } else {
+            GraphicsConfig::default().ui_scale
+        };
+    }
+
+    pub fn bloom_settings(&self) -> Option<BloomSettings> {
+        return match self.bloom {
+            BloomPreset::Off => None,
+            BloomPreset::Custom => Some(self.custom_bloom.to_settings()),
+            preset => preset.values().map(|v| v.to_settings()),
+        };
+    }
+
+    fn window_mode(&self) -> WindowMode {
+        return if self.fullscreen { WindowMode::BorderlessFullscreen } else { WindowMode::Windowed };
+    }
+
+    fn present_mode(&self) -> PresentMode {
+        return if self.vsync { PresentMode::AutoVsync } else { PresentMode::AutoNoVsync };
+    }
+
+    /// The window to open with, so it doesn't flicker from the defaults on startup.
+    pub fn window(&self, title: &str) -> WindowDescriptor {
+        return WindowDescriptor {
+            title: title.into(),
+            width: self.resolution.0,
+            height: self.resolution.1,
+            mode: self.window_mode(),
+            present_mode: self.present_mode(),
+            ..default()
+        };
+    }
+}
+
+/// Applies the graphics settings whenever they change, window and all.
+pub fn apply(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut msaa: ResMut<Msaa>,
+    mut egui_settings: ResMut<EguiSettings>,
+    mut windows: ResMut<Windows>,
+    cameras: Query<Entity, With<CameraRig>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    let graphics = &settings.graphics;
+
+    if msaa.samples != graphics.msaa {
+        msaa.samples = graphics.msaa;
+    }
+    if egui_settings.scale_factor != graphics.ui_scale as f64 {
+        egui_settings.scale_factor = graphics.ui_scale as f64;
+    }
+    for camera in cameras.iter() {
+        match graphics.bloom_settings() {
+            Some(bloom) => commands.entity(camera).insert(bloom),
+            None => commands.entity(camera).remove::<BloomSettings>(),
+        };
+    }
+
+    if let Some(window) = windows.get_primary_mut() {
+        if window.mode() != graphics.window_mode() {
+            window.set_mode(graphics.window_mode());
+        }
+        if window.present_mode() != graphics.present_mode() {
+            window.set_present_mode(graphics.present_mode());
+        }
+        // set_resolution skips the window command itself when nothing changed
+        if !graphics.fullscreen {
+            window.set_resolution(graphics.resolution.0, graphics.resolution.1);
+        }
+    }
+}
+
+/// Sleeps off whatever is left of the frame when there's an FPS cap.
+pub fn limit_fps(settings: Res<Settings>, mut last_frame: Local<Option<Instant>>) {
+    if settings.graphics.fps_cap > 0 {
+        let frame_time = Duration::from_secs_f64(1.0 / settings.graphics.fps_cap as f64);
+        if let Some(last) = *last_frame {
+            let elapsed = last.elapsed();
+            if elapsed < frame_time {
+                std::thread::sleep(frame_time - elapsed);
+            }
+        }
+    }
+    *last_frame = Some(Instant::now());
+}
+
+#[test]
+fn sanitize_snaps_msaa_and_clamps_ui_scale() {
+    let mut graphics = GraphicsConfig {
+        msaa: 3,
+        ui_scale: 10.0,
+        ..default()
+    };
+    graphics.sanitize();
+    assert_eq!(graphics.msaa, 4);
+    assert_eq!(graphics.ui_scale, 3.0);
+
+    graphics.msaa = 0;
+    graphics.ui_scale = f32::NAN;
+    graphics.sanitize();
+    assert_eq!(graphics.msaa, 1);
+    assert_eq!(graphics.ui_scale, 2.0);
+}
+
+#[test]
+fn bloom_presets() {
+    let mut graphics = GraphicsConfig::default();
+    graphics.bloom = BloomPreset::Off;
+    assert!(graphics.bloom_settings().is_none());
+    graphics.bloom = BloomPreset::High;
+    assert_eq!(graphics.bloom_settings().unwrap().intensity, 0.6);
+    graphics.bloom = BloomPreset::Custom;
+    graphics.custom_bloom.intensity = 1.5;
+    assert_eq!(graphics.bloom_settings().unwrap().intensity, 1.5);
+}
@@ -0,0 +1,289 @@
+use rand::Rng;
+
+use crate::*;
+
+// damage over time from spikes and lava is dealt in ticks rather than every frame
+const HAZARD_INTERVAL: f32 = 0.5;
+const BARREL_RADIUS: f32 = 150.0;
+const BARREL_DAMAGE: f32 = 120.0;
+const CRATE_DROP_CHANCE: f64 = 0.35;
+const FLASH_TIME: f32 = 0.2;
+// damage at the very edge of the blast, as a fraction of the damage at its center
+const EDGE_DAMAGE: f32 = 0.25;
+// outward knockback per point of damage dealt
+const EXPLOSION_KNOCKBACK: f32 = 5.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropKind {
+    Barrel,
+    Crate,
+}
+
+impl PropKind {
+    /// Props are written into map files on top of floor tiles.
+    pub fn from_char(c: char) -> Option<PropKind> {
+        match c {
+            'B' => Some(PropKind::Barrel),
+            'C' => Some(PropKind::Crate),
+            _ => None,
+        }
+    }
+
+    fn health(&self) -> f32 {
+        match self {
+            PropKind::Barrel => 60.0,
+            PropKind::Crate => 100.0,
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            PropKind::Barrel => Color::rgb(0.9, 0.2, 0.1),
+            PropKind::Crate => Color::rgb(0.55, 0.35, 0.15),
+        }
+    }
+}
+
+/// Something on the map that can be shot to pieces.
+#[derive(Component)]
+pub struct Prop {
+    pub kind: PropKind,
+    pub health: f32,
+    // whoever broke it gets the credit for what it does next
+    pub last_hit_by: Option<Entity>,
+}
+
+#[derive(Bundle)]
+pub struct PropBundle {
+    prop: Prop,
+    collider: Collider,
+    sensor: Sensor,
+
+    #[bundle]
+    sprite: SpriteBundle,
+}
+
+impl PropBundle {
+    pub fn new(kind: PropKind, pos: Vec2, size: f32) -> Self {
+        return PropBundle {
+            prop: Prop {
+                kind,
+                health: kind.health(),
+                last_hit_by: None,
+            },
+            collider: Collider::cuboid(size / 2.0, size / 2.0),
+            sensor: Sensor,
+            sprite: SpriteBundle {
+                sprite: Sprite {
+                    color: kind.color(),
+                    custom_size: Some(Vec2::splat(size)),
+                    ..default()
+                },
+                transform: Transform::from_translation(pos.extend(z_from_y(pos.y))),
+                ..default()
+            },
+        };
+    }
+}
+
+/// Marks the sprites drawn over spike, lava and mud tiles.
+#[derive(Component, Default)]
+pub struct Hazard;
+
+pub fn hazard_sprite(tile: Tile, pos: Vec2, size: f32) -> Option<(SpriteBundle, Hazard)> {
+    let color = match tile {
+        Tile::Spikes => Color::rgb(0.45, 0.45, 0.5),
+        Tile::Lava => LAVA_GLOW,
+        Tile::Mud => Color::rgb(0.3, 0.2, 0.1),
+        Tile::Floor | Tile::Wall => return None,
+    };
+    let sprite = SpriteBundle {
+        sprite: Sprite {
+            color,
+            custom_size: Some(Vec2::splat(size)),
+            ..default()
+        },
+        transform: Transform::from_translation(pos.extend(20.0)),
+        ..default()
+    };
+    return Some((sprite, Hazard));
+}
+
+/// Area damage to everything in `radius`, falling off towards the edge. Barrels hurt
+/// players too, but a player's own explosive shots don't.
+pub struct Explosion {
+    pub position: Vec2,
+    pub radius: f32,
+    pub damage: f32,
+    pub source: Option<Entity>,
+    pub hurts_players: bool,
+    // what the shot hit directly, which already took its damage and isn't caught in the blast
+    pub exclude: Option<Entity>,
+}
+
+impl Explosion {
+    /// Damage dealt to something `distance` away from the center.
+    pub fn damage_at(&self, distance: f32) -> f32 {
+        let t = (distance / self.radius).clamp(0.0, 1.0);
+        return self.damage * lerp(1.0, EDGE_DAMAGE, t);
+    }
+}
+
+#[derive(Component)]
+pub struct ExplosionFlash(Timer);
+
+#[derive(Resource)]
+pub struct HazardClock(Timer);
+
+impl Default for HazardClock {
+    fn default() -> Self {
+        return HazardClock(Timer::from_seconds(HAZARD_INTERVAL, TimerMode::Repeating));
+    }
+}
+
+/// Hurts anyone standing on spikes or lava.
+pub fn tick_hazards(
+    time: Res<Time>,
+    arena: Res<Arena>,
+    mut clock: ResMut<HazardClock>,
+    mut damages: EventWriter<Damage>,
+    players: Query<(Entity, &Player)>,
+    enemies: Query<(Entity, &Enemy)>,
+) {
+    if !clock.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    let standing = players
+        .iter()
+        .filter(|(_, p)| !p.downed)
+        .map(|(e, p)| (e, p.position))
+        .chain(enemies.iter().map(|(e, enemy)| (e, enemy.position)));
+    for (entity, position) in standing {
+        let dps = arena.tile_at(position).damage_per_second();
+        if dps > 0.0 {
+            damages.send(Damage {
+                target: entity,
+                amount: dps * HAZARD_INTERVAL,
+                kind: DamageKind::Hazard,
+                source: None,
+                shot: None,
+                crit: false,
+                impulse: Vec2::ZERO,
+            });
+        }
+    }
+}
+
+/// Breaks props that ran out of health: barrels go off, crates might drop something.
+pub fn tick_props(
+    mut commands: Commands,
+    mut colors: ResMut<Assets<ColorMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut explosions: EventWriter<Explosion>,
+    props: Query<(Entity, &Prop, &Transform)>,
+) {
+    for (entity, prop, transform) in props.iter() {
+        if prop.health > 0.0 {
+            continue;
+        }
+        commands.entity(entity).despawn();
+        let position = transform.translation.truncate();
+        match prop.kind {
+            PropKind::Barrel => explosions.send(Explosion {
+                position,
+                radius: BARREL_RADIUS,
+                damage: BARREL_DAMAGE,
+                source: prop.last_hit_by,
+                hurts_players: true,
+                exclude: None,
+            }),
+            PropKind::Crate => {
+                if rand::thread_rng().gen_bool(CRATE_DROP_CHANCE) {
+                    commands.spawn(PickupBundle::from_kind(position, PickupKind::random(), 0, &mut colors, &mut meshes));
+                }
+            }
+        }
+    }
+}
+
+pub fn explode(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut explosions: EventReader<Explosion>,
+    mut damages: EventWriter<Damage>,
+    mut shakes: EventWriter<ScreenShake>,
+    mut sounds: EventWriter<PlaySound>,
+    mut pool: ResMut<Pool<ExplosionFlash>>,
+    mut flashes: Query<(Entity, &mut ExplosionFlash, &mut Sprite), Without<Parked>>,
+    targets: Query<&GlobalTransform, Or<(With<Enemy>, With<Player>, With<Prop>)>>,
+    players: Query<(), With<Player>>,
+    rapier_ctx: Res<RapierContext>,
+) {
+    for explosion in explosions.iter() {
+        rapier_ctx.intersections_with_shape(
+            explosion.position,
+            0.0,
+            &Collider::ball(explosion.radius),
+            QueryFilter::default(),
+            |entity| {
+                if explosion.exclude == Some(entity) || (!explosion.hurts_players && players.contains(entity)) {
+                    return true;
+                }
+                if let Ok(transform) = targets.get(entity) {
+                    let offset = transform.translation().truncate() - explosion.position;
+                    let amount = explosion.damage_at(offset.length());
+                    damages.send(Damage {
+                        target: entity,
+                        amount,
+                        kind: DamageKind::Explosion,
+                        source: explosion.source,
+                        shot: None,
+                        crit: false,
+                        impulse: offset.normalize_or_zero() * amount * EXPLOSION_KNOCKBACK,
+                    });
+                }
+                true
+            },
+        );
+        // small blasts from explosive shots shouldn't rattle the screen as much as a barrel
+        shakes.send(ScreenShake(0.6 * (explosion.radius / BARREL_RADIUS).min(1.0)));
+        sounds.send(PlaySound::at(Sfx::Explosion, explosion.position));
+        pool.spawn(&mut commands, (
+            SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgb(3.0, 1.5, 0.4),
+                    custom_size: Some(Vec2::splat(explosion.radius * 2.0)),
+                    ..default()
+                },
+                transform: Transform::from_translation(explosion.position.extend(950.0)),
+                ..default()
+            },
+            ExplosionFlash(Timer::from_seconds(FLASH_TIME, TimerMode::Once)),
+        ));
+    }
+
+    for (entity, mut flash, mut sprite) in flashes.iter_mut() {
+        flash.0.tick(time.delta());
+        sprite.color.set_a(flash.0.percent_left());
+        if flash.0.finished() {
+            pool.release(&mut commands, entity);
+        }
+    }
+}
+
+#[test]
+fn explosion_falls_off_towards_edge() {
+    let explosion = Explosion {
+        position: Vec2::ZERO,
+        radius: 100.0,
+        damage: 100.0,
+        source: None,
+        hurts_players: true,
+        exclude: None,
+    };
+    assert_eq!(explosion.damage_at(0.0), 100.0);
+    assert_eq!(explosion.damage_at(100.0), 100.0 * EDGE_DAMAGE);
+    assert!(explosion.damage_at(50.0) < explosion.damage_at(10.0));
+    // things poking into the blast from outside its radius still take the edge damage
+    assert_eq!(explosion.damage_at(150.0), 100.0 * EDGE_DAMAGE);
+}
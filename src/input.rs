@@ -0,0 +1,473 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use bevy_egui::EguiContext;
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use crate::{settings::*, *};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, EnumIter, Serialize, Deserialize)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Fire,
+    RapidFire,
+    Dash,
+    Pause,
+    Map,
+}
+
+impl Action {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Action::MoveUp => "Move Up",
+            Action::MoveDown => "Move Down",
+            Action::MoveLeft => "Move Left",
+            Action::MoveRight => "Move Right",
+            Action::Fire => "Fire",
+            Action::RapidFire => "Rapid Fire",
+            Action::Dash => "Dash",
+            Action::Pause => "Pause",
+            Action::Map => "Map",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButtonType),
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "{:?}", key),
+            Binding::Mouse(button) => write!(f, "Mouse {:?}", button),
+            Binding::Gamepad(button) => write!(f, "Pad {:?}", button),
+        }
+    }
+}
+
+impl Binding {
+    fn pressed(&self, devices: &Devices, device: InputDevice) -> bool {
+        match *self {
+            Binding::Key(key) => device.uses_keyboard() && devices.keys.pressed(key),
+            Binding::Mouse(button) => device.uses_keyboard() && devices.mouse.pressed(button),
+            Binding::Gamepad(button_type) => devices
+                .gamepads
+                .iter()
+                .filter(|&gamepad| device.uses_gamepad(gamepad))
+                .any(|gamepad| devices.buttons.pressed(GamepadButton::new(gamepad, button_type))),
+        }
+    }
+}
+
+struct Devices<'a> {
+    keys: &'a Input<KeyCode>,
+    mouse: &'a Input<MouseButton>,
+    buttons: &'a Input<GamepadButton>,
+    gamepads: &'a Gamepads,
+}
+
+/// Which physical devices drive a player.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum InputDevice {
+    // single player: keyboard, mouse and every pad
+    #[default]
+    Any,
+    KeyboardMouse,
+    Gamepad(Gamepad),
+    // co-op player without a connected pad, picks up the next one plugged in
+    WaitingForPad,
+}
+
+impl InputDevice {
+    fn uses_keyboard(&self) -> bool {
+        matches!(self, InputDevice::Any | InputDevice::KeyboardMouse)
+    }
+
+    fn uses_gamepad(&self, gamepad: Gamepad) -> bool {
+        match *self {
+            InputDevice::Any => true,
+            InputDevice::KeyboardMouse | InputDevice::WaitingForPad => false,
+            InputDevice::Gamepad(g) => g == gamepad,
+        }
+    }
+}
+
+impl fmt::Display for InputDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputDevice::Any => write!(f, "Any"),
+            InputDevice::KeyboardMouse => write!(f, "Keyboard & Mouse"),
+            InputDevice::Gamepad(gamepad) => write!(f, "Gamepad {}", gamepad.id),
+            InputDevice::WaitingForPad => write!(f, "Waiting for a gamepad"),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Bindings {
+    map: HashMap<Action, Vec<Binding>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let mut bindings = Bindings {
+            map: HashMap::new(),
+        };
+        use Binding::*;
+        bindings.set(Action::MoveUp, &[Key(KeyCode::Up), Key(KeyCode::W), Gamepad(GamepadButtonType::DPadUp)]);
+        bindings.set(Action::MoveDown, &[Key(KeyCode::Down), Key(KeyCode::S), Gamepad(GamepadButtonType::DPadDown)]);
+        bindings.set(Action::MoveLeft, &[Key(KeyCode::Left), Key(KeyCode::A), Gamepad(GamepadButtonType::DPadLeft)]);
+        bindings.set(Action::MoveRight, &[Key(KeyCode::Right), Key(KeyCode::D), Gamepad(GamepadButtonType::DPadRight)]);
+        bindings.set(Action::Fire, &[Key(KeyCode::Space), Mouse(MouseButton::Left), Gamepad(GamepadButtonType::RightTrigger2)]);
+        bindings.set(Action::RapidFire, &[Key(KeyCode::LShift), Gamepad(GamepadButtonType::RightTrigger)]);
+        bindings.set(Action::Dash, &[Mouse(MouseButton::Right), Gamepad(GamepadButtonType::South)]);
+        bindings.set(Action::Pause, &[Key(KeyCode::Escape), Gamepad(GamepadButtonType::Start)]);
+        bindings.set(Action::Map, &[Key(KeyCode::Tab), Key(KeyCode::M), Gamepad(GamepadButtonType::Select)]);
+        return bindings;
+    }
+}
+
+impl Bindings {
+    pub fn get(&self, action: Action) -> &[Binding] {
+        return self.map.get(&action).map(|b| b.as_slice()).unwrap_or(&[]);
+    }
+
+    pub fn set(&mut self, action: Action, bindings: &[Binding]) {
+        self.map.insert(action, bindings.to_vec());
+    }
+
+    pub fn add(&mut self, action: Action, binding: Binding) {
+        let bindings = self.map.entry(action).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn add_missing_defaults(&mut self) {
+        let defaults = Bindings::default();
+        for action in Action::iter() {
+            if !self.map.contains_key(&action) {
+                self.set(action, defaults.get(action));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, action: Action, index: usize) {
+        if let Some(bindings) = self.map.get_mut(&action) {
+            if index < bindings.len() {
+                bindings.remove(index);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StickConfig {
+    pub move_deadzone: f32,
+    pub aim_deadzone: f32,
+    // distance from the player the cursor is placed at when aiming with a stick
+    pub aim_radius: f32,
+}
+
+impl Default for StickConfig {
+    fn default() -> Self {
+        StickConfig {
+            move_deadzone: 0.15,
+            aim_deadzone: 0.25,
+            aim_radius: 150.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum AimMode {
+    #[default]
+    Mouse,
+    Stick,
+}
+
+#[derive(Component, Default)]
+pub struct ActionState {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+
+    // analog stick values with deadzones applied, magnitude in 0..=1
+    pub move_stick: Vec2,
+    pub aim_stick: Vec2,
+    // last aim direction pushed on the stick, kept when it's released
+    pub aim_dir: Vec2,
+    pub aim_mode: AimMode,
+}
+
+impl ActionState {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+}
+
+/// Rescales a stick so the deadzone maps to 0 and the rim to 1, keeping the direction.
+pub fn apply_deadzone(stick: Vec2, deadzone: f32) -> Vec2 {
+    let len = stick.length();
+    if len <= deadzone || deadzone >= 1.0 {
+        return Vec2::ZERO;
+    }
+    return stick / len * ((len - deadzone) / (1.0 - deadzone)).min(1.0);
+}
+
+fn read_stick(axes: &Axis<GamepadAxis>, gamepad: Gamepad, x: GamepadAxisType, y: GamepadAxisType) -> Vec2 {
+    return vec2(
+        axes.get(GamepadAxis::new(gamepad, x)).unwrap_or(0.0),
+        axes.get(GamepadAxis::new(gamepad, y)).unwrap_or(0.0),
+    );
+}
+
+/// Hands connected pads to co-op players in slot order. Players whose pad was unplugged, or who
+/// never had one, take the next free pad as soon as it connects.
+pub fn assign_gamepads(gamepads: Res<Gamepads>, mut players: Query<(&Player, &mut InputDevice)>) {
+    let mut players: Vec<_> = players.iter_mut().collect();
+    players.sort_by_key(|(player, _)| player.slot);
+
+    let taken: Vec<Gamepad> = players
+        .iter()
+        .filter_map(|(_, device)| match **device {
+            InputDevice::Gamepad(gamepad) if gamepads.contains(gamepad) => Some(gamepad),
+            _ => None,
+        })
+        .collect();
+    let mut free: Vec<Gamepad> = gamepads.iter().filter(|g| !taken.contains(g)).collect();
+    free.sort_by_key(|g| g.id);
+    let mut free = free.into_iter();
+
+    for (_, device) in players.iter_mut() {
+        let needs_pad = match **device {
+            InputDevice::WaitingForPad => true,
+            InputDevice::Gamepad(gamepad) => !gamepads.contains(gamepad),
+            _ => false,
+        };
+        if needs_pad {
+            // keep the old pad if nothing is free, so it works again if it comes back
+            if let Some(gamepad) = free.next() {
+                **device = InputDevice::Gamepad(gamepad);
+            }
+        }
+    }
+}
+
+pub fn update_actions(
+    settings: Res<Settings>,
+    menu: Res<SettingsMenu>,
+    console: Res<Console>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    gamepads: Res<Gamepads>,
+    mut gamepad_events: EventReader<GamepadEvent>,
+    mut cursor_moved: EventReader<CursorMoved>,
+    mut players: Query<(&InputDevice, &mut ActionState)>,
+) {
+    let devices = Devices {
+        keys: &keys,
+        mouse: &mouse,
+        buttons: &buttons,
+        gamepads: &gamepads,
+    };
+
+    let mouse_moved = cursor_moved.iter().last().is_some();
+    let mut pad_connected = false;
+    let mut pads_gone = false;
+    for event in gamepad_events.iter() {
+        match event.event_type {
+            GamepadEventType::Connected(_) => pad_connected = true,
+            GamepadEventType::Disconnected => pads_gone = gamepads.iter().all(|g| g == event.gamepad),
+            _ => {}
+        }
+    }
+
+    for (&device, mut actions) in players.iter_mut() {
+        let mut pressed = HashSet::new();
+        // don't leak the key being bound, or console typing, into gameplay
+        if menu.listening.is_none() && !console.open {
+            for action in Action::iter() {
+                if settings.bindings.get(action).iter().any(|b| b.pressed(&devices, device)) {
+                    pressed.insert(action);
+                }
+            }
+        }
+
+        actions.just_pressed = pressed.difference(&actions.pressed).cloned().collect();
+        actions.pressed = pressed;
+
+        // sticks: take whichever pad is pushed furthest
+        let mut move_stick = Vec2::ZERO;
+        let mut aim_stick = Vec2::ZERO;
+        for gamepad in gamepads.iter().filter(|&g| device.uses_gamepad(g)) {
+            let left = read_stick(&axes, gamepad, GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY);
+            let right = read_stick(&axes, gamepad, GamepadAxisType::RightStickX, GamepadAxisType::RightStickY);
+            if left.length() > move_stick.length() {
+                move_stick = left;
+            }
+            if right.length() > aim_stick.length() {
+                aim_stick = right;
+            }
+        }
+        actions.move_stick = apply_deadzone(move_stick, settings.sticks.move_deadzone);
+        actions.aim_stick = apply_deadzone(aim_stick, settings.sticks.aim_deadzone);
+        if actions.aim_stick != Vec2::ZERO {
+            actions.aim_dir = actions.aim_stick.normalize();
+        }
+
+        actions.aim_mode = match device {
+            InputDevice::KeyboardMouse => AimMode::Mouse,
+            InputDevice::Gamepad(_) | InputDevice::WaitingForPad => AimMode::Stick,
+            // switch aim to whichever device was touched last
+            InputDevice::Any => {
+                if mouse_moved || pads_gone {
+                    AimMode::Mouse
+                } else if pad_connected || actions.aim_stick != Vec2::ZERO {
+                    AimMode::Stick
+                } else {
+                    actions.aim_mode
+                }
+            }
+        };
+    }
+}
+
+pub fn capture_binding(
+    mut egui_context: ResMut<EguiContext>,
+    mut menu: ResMut<SettingsMenu>,
+    mut settings: ResMut<Settings>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    buttons: Res<Input<GamepadButton>>,
+    // what was being listened for last frame, to spot the frame listening starts
+    mut was_listening: Local<Option<Action>>,
+) {
+    let just_started = menu.listening != *was_listening;
+    *was_listening = menu.listening;
+    if let Some(action) = menu.listening {
+        // escape always cancels so the menu can't get stuck waiting
+        if keys.just_pressed(KeyCode::Escape) {
+            menu.listening = None;
+            return;
+        }
+
+        // the click on the "+" button, or anything else in the menu, isn't the new binding
+        let mouse_free = !just_started && !egui_context.ctx_mut().wants_pointer_input();
+        let binding = keys
+            .get_just_pressed()
+            .next()
+            .map(|&k| Binding::Key(k))
+            .or(mouse.get_just_pressed().next().filter(|_| mouse_free).map(|&m| Binding::Mouse(m)))
+            .or(buttons.get_just_pressed().next().map(|b| Binding::Gamepad(b.button_type)));
+
+        if let Some(binding) = binding {
+            settings.bindings.add(action, binding);
+            menu.listening = None;
+        }
+    }
+}
+
+#[test]
+fn bindings_add_remove_and_fill_in_defaults() {
+    let mut bindings = Bindings::default();
+    let fire = bindings.get(Action::Fire).to_vec();
+    assert!(fire.contains(&Binding::Mouse(MouseButton::Left)));
+
+    // adding one that's already there does nothing
+    bindings.add(Action::Fire, Binding::Key(KeyCode::Space));
+    assert_eq!(bindings.get(Action::Fire), fire.as_slice());
+    bindings.add(Action::Fire, Binding::Key(KeyCode::F));
+    assert_eq!(bindings.get(Action::Fire).last(), Some(&Binding::Key(KeyCode::F)));
+
+    bindings.remove(Action::Fire, 0);
+    assert_eq!(bindings.get(Action::Fire)[0], fire[1]);
+    // out of range is ignored
+    bindings.remove(Action::Fire, 99);
+    assert_eq!(bindings.get(Action::Fire).len(), fire.len());
+
+    // a settings file from before an action existed gets that action's defaults, and
+    // nothing else is touched
+    let mut old = Bindings { map: HashMap::new() };
+    old.set(Action::Dash, &[]);
+    old.add_missing_defaults();
+    assert!(old.get(Action::Dash).is_empty());
+    assert_eq!(old.get(Action::Map), Bindings::default().get(Action::Map));
+}
+
+#[test]
+fn deadzone_rescales_the_stick() {
+    assert_eq!(apply_deadzone(vec2(0.1, 0.0), 0.2), Vec2::ZERO);
+    assert_eq!(apply_deadzone(vec2(0.0, 0.2), 0.2), Vec2::ZERO);
+    // halfway between the deadzone and the rim is half speed, same direction
+    let half = apply_deadzone(vec2(0.0, -0.6), 0.2);
+    assert!((half - vec2(0.0, -0.5)).length() < 1e-6, "{:?}", half);
+    // past the rim, as some pads report on the diagonals, is capped at 1
+    let diagonal = apply_deadzone(vec2(1.0, 1.0), 0.2);
+    assert!((diagonal.length() - 1.0).abs() < 1e-6);
+    assert!((diagonal.x - diagonal.y).abs() < 1e-6);
+    assert_eq!(apply_deadzone(vec2(1.0, 0.0), 1.0), Vec2::ZERO);
+}
+
+#[test]
+fn pads_go_to_players_that_need_one() {
+    use bevy::{
+        ecs::schedule::SystemStage,
+        input::gamepad::{gamepad_connection_system, GamepadInfo},
+    };
+
+    let mut world = World::new();
+    world.init_resource::<Gamepads>();
+    world.init_resource::<Events<GamepadEvent>>();
+    let mut stage = SystemStage::single_threaded()
+        .with_system(gamepad_connection_system)
+        .with_system(assign_gamepads.after(gamepad_connection_system));
+
+    let mut spawn = |slot, device| {
+        let mut player = Player::default();
+        player.slot = slot;
+        return world.spawn((player, device)).id();
+    };
+    let keyboard = spawn(0, InputDevice::KeyboardMouse);
+    let second = spawn(1, InputDevice::WaitingForPad);
+    let third = spawn(2, InputDevice::WaitingForPad);
+
+    let mut connect = |world: &mut World, id| {
+        let info = GamepadInfo { name: "pad".into() };
+        let event = GamepadEvent::new(Gamepad::new(id), GamepadEventType::Connected(info));
+        world.resource_mut::<Events<GamepadEvent>>().send(event);
+    };
+    let device = |world: &World, e| *world.get::<InputDevice>(e).unwrap();
+
+    // ids don't have to start at zero or be contiguous
+    connect(&mut world, 3);
+    stage.run(&mut world);
+    assert_eq!(device(&world, keyboard), InputDevice::KeyboardMouse);
+    assert_eq!(device(&world, second), InputDevice::Gamepad(Gamepad::new(3)));
+    assert_eq!(device(&world, third), InputDevice::WaitingForPad);
+
+    // a dropped pad is replaced by the next one plugged in
+    let event = GamepadEvent::new(Gamepad::new(3), GamepadEventType::Disconnected);
+    world.resource_mut::<Events<GamepadEvent>>().send(event);
+    connect(&mut world, 7);
+    stage.run(&mut world);
+    assert_eq!(device(&world, second), InputDevice::Gamepad(Gamepad::new(7)));
+    assert_eq!(device(&world, third), InputDevice::WaitingForPad);
+}
@@ -0,0 +1,163 @@
+use bevy_egui::{egui, EguiContext};
+use strum::IntoEnumIterator;
+
+use crate::*;
+
+/// Turns Rapier's collider outlines on and off with the dev tools.
+pub fn show_colliders(dev: Res<DevTools>, mut debug_render: ResMut<DebugRenderContext>) {
+    if dev.is_changed() && debug_render.enabled != dev.colliders {
+        debug_render.enabled = dev.colliders;
+    }
+}
+
+/// Middle click on an enemy, bullet or pickup with the dev overlay up to inspect it.
+pub fn pick(
+    mut egui_context: ResMut<EguiContext>,
+    mut dev: ResMut<DevTools>,
+    game: Res<Game>,
+    mouse: Res<Input<MouseButton>>,
+    rapier_ctx: Res<RapierContext>,
+    inspectable: Query<(), (Or<(With<Enemy>, With<Bullet>, With<Pickup>)>, Without<Parked>)>,
+) {
+    if !dev.overlay || !mouse.just_pressed(MouseButton::Middle) || egui_context.ctx_mut().is_pointer_over_area() {
+        return;
+    }
+    let mut picked = None;
+    rapier_ctx.intersections_with_point(game.mouse_world_pos, QueryFilter::default(), |entity| {
+        if inspectable.contains(entity) {
+            picked = Some(entity);
+            return false;
+        }
+        return true;
+    });
+    // clicking empty space keeps the current selection
+    if picked.is_some() {
+        dev.inspected = picked;
+    }
+}
+
+/// Lets go of a pooled entity once it's handed back, since the pool gives it out again as
+/// something else. Runs after the update so it also catches one parked and reused within
+/// a frame, which only shows up as a removed `Parked`.
+pub fn forget_parked(
+    mut dev: ResMut<DevTools>,
+    parked: Query<(), With<Parked>>,
+    unparked: RemovedComponents<Parked>,
+) {
+    if let Some(entity) = dev.inspected {
+        if parked.contains(entity) || unparked.iter().any(|e| e == entity) {
+            dev.inspected = None;
+        }
+    }
+}
+
+pub fn draw_inspector(
+    mut egui_context: ResMut<EguiContext>,
+    mut dev: ResMut<DevTools>,
+    mut enemies: Query<&mut Enemy, Without<Parked>>,
+    mut bullets: Query<&mut Bullet, Without<Parked>>,
+    mut pickups: Query<&mut Pickup, Without<Parked>>,
+) {
+    let entity = match dev.inspected {
+        Some(entity) if dev.overlay => entity,
+        _ => return,
+    };
+
+    let mut open = true;
+    egui::Window::new("Inspector")
+        .open(&mut open)
+        .anchor(egui::Align2::RIGHT_CENTER, [-10.0, 0.0])
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.label(format!("{:?}", entity));
+            if let Ok(mut enemy) = enemies.get_mut(entity) {
+                enemy_fields(ui, &mut enemy);
+            } else if let Ok(mut bullet) = bullets.get_mut(entity) {
+                bullet_fields(ui, &mut bullet);
+            } else if let Ok(mut pickup) = pickups.get_mut(entity) {
+                pickup_fields(ui, &mut pickup);
+            } else {
+                ui.label("Gone");
+            }
+        });
+    if !open {
+        dev.inspected = None;
+    }
+}
+
+fn enemy_fields(ui: &mut egui::Ui, enemy: &mut Enemy) {
+    ui.heading(if enemy.boss { "Boss" } else { "Enemy" });
+    ui.horizontal(|ui| {
+        ui.add(egui::DragValue::new(&mut enemy.health).speed(1.0).clamp_range(0.0..=f32::MAX));
+        ui.label(format!("/ {} Health", enemy.max_health));
+    });
+    let mut hit_timer = enemy.hit_timer.elapsed_secs();
+    ui.horizontal(|ui| {
+        if ui.add(egui::DragValue::new(&mut hit_timer).speed(0.01).clamp_range(0.0..=10.0)).changed() {
+            enemy.hit_timer.set_elapsed(std::time::Duration::from_secs_f32(hit_timer));
+        }
+        ui.label(format!("Hit Timer (attacks every {:.2}s)", enemy.hit_interval.as_secs_f32()));
+    });
+    ui.add(egui::Slider::new(&mut enemy.speed, 0.0..=1000.0).text("Speed"));
+    ui.add(egui::Slider::new(&mut enemy.damage, 0.0..=100.0).text("Damage"));
+    ui.add(egui::Slider::new(&mut enemy.mass, 0.1..=20.0).text("Mass"));
+    ui.add(egui::Slider::new(&mut enemy.stun, 0.0..=5.0).text("Stun"));
+    ui.label(format!("Position: {:.0}, {:.0}", enemy.position.x, enemy.position.y));
+    ui.label(format!("Knockback: {:.0}, {:.0}", enemy.knockback.x, enemy.knockback.y));
+}
+
+fn bullet_fields(ui: &mut egui::Ui, bullet: &mut Bullet) {
+    ui.heading("Bullet");
+    ui.add(egui::Slider::new(&mut bullet.piercing, 0..=20).text("Piercing"));
+    ui.add(egui::Slider::new(&mut bullet.damage, 0.0..=500.0).text("Damage"));
+    ui.add(egui::Slider::new(&mut bullet.explosion_radius, 0.0..=300.0).text("Explosion Radius"));
+    ui.add(egui::Slider::new(&mut bullet.chain_jumps, 0..=10).text("Chain Jumps"));
+    ui.checkbox(&mut bullet.crit, "Crit");
+    ui.label(format!("Velocity: {:.0}, {:.0}", bullet.velocity.x, bullet.velocity.y));
+    ui.horizontal(|ui| {
+        ui.label(format!("Hit {} enemies", bullet.hit_enemies.len()));
+        if ui.button("Clear").clicked() {
+            bullet.hit_enemies.clear();
+        }
+    });
+    for enemy in bullet.hit_enemies.iter() {
+        ui.label(format!("  {:?}", enemy));
+    }
+}
+
+fn pickup_fields(ui: &mut egui::Ui, pickup: &mut Pickup) {
+    ui.heading("Pickup");
+    egui::ComboBox::from_label("Kind")
+        .selected_text(format!("{:?}", pickup.kind))
+        .show_ui(ui, |ui| {
+            for kind in PickupKind::iter() {
+                let text = format!("{:?}", kind);
+                ui.selectable_value(&mut pickup.kind, kind, text);
+            }
+        });
+    ui.add(egui::Slider::new(&mut pickup.price, 0..=500).text("Price"));
+}
+
+#[test]
+fn forgets_entities_handed_back_to_the_pool() {
+    use bevy::ecs::system::CommandQueue;
+
+    let mut app = App::new();
+    app.init_resource::<DevTools>()
+        .init_resource::<Pool<Bullet>>()
+        .add_system_to_stage(CoreStage::PostUpdate, forget_parked);
+    let entity = app.world.spawn(Bullet::default()).id();
+    app.world.resource_mut::<DevTools>().inspected = Some(entity);
+    app.update();
+    assert_eq!(app.world.resource::<DevTools>().inspected, Some(entity));
+
+    // parked and handed straight back out as a new bullet within the same frame
+    app.world.resource_scope(|world, mut pool: Mut<Pool<Bullet>>| {
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, world);
+        pool.release(&mut commands, entity);
+        assert_eq!(pool.spawn(&mut commands, Bullet::default()), Some(entity));
+        queue.apply(world);
+    });
+    app.update();
+    assert_eq!(app.world.resource::<DevTools>().inspected, None);
+}
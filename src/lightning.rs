@@ -0,0 +1,138 @@
+use rand::Rng;
+
+use crate::*;
+
+// how far the lightning can jump from one enemy to the next
+const CHAIN_RANGE: f32 = 250.0;
+// each jump does this fraction of the one before it
+const CHAIN_DECAY: f32 = 0.7;
+const ARC_TIME: f32 = 0.15;
+const ARC_SEGMENTS: usize = 5;
+const ARC_WIDTH: f32 = 4.0;
+
+/// Lightning that starts at `first` and jumps on to up to `jumps` more enemies nearby,
+/// never hitting the same one twice.
+pub struct ChainLightning {
+    pub first: Entity,
+    pub jumps: i32,
+    pub damage: f32,
+    pub source: Option<Entity>,
+}
+
+/// One jagged segment of a drawn arc, fading out.
+#[derive(Component)]
+pub struct LightningArc(Timer);
+
+/// Damage for the `jump`th enemy the lightning reaches.
+pub fn chain_damage(damage: f32, jump: i32) -> f32 {
+    return damage * CHAIN_DECAY.powi(jump);
+}
+
+pub fn chain(
+    mut commands: Commands,
+    mut chains: EventReader<ChainLightning>,
+    mut damages: EventWriter<Damage>,
+    mut pool: ResMut<Pool<LightningArc>>,
+    enemies: Query<&Enemy>,
+    rapier_ctx: Res<RapierContext>,
+) {
+    for lightning in chains.iter() {
+        let mut from = match enemies.get(lightning.first) {
+            Ok(enemy) => enemy.position,
+            Err(_) => continue,
+        };
+        let mut hit = vec![lightning.first];
+
+        for jump in 1..=lightning.jumps {
+            let mut next: Option<(Entity, Vec2)> = None;
+            rapier_ctx.intersections_with_shape(from, 0.0, &Collider::ball(CHAIN_RANGE), QueryFilter::default(), |entity| {
+                if hit.contains(&entity) {
+                    return true;
+                }
+                if let Ok(enemy) = enemies.get(entity) {
+                    let closer = match next {
+                        Some((_, pos)) => from.distance(enemy.position) < from.distance(pos),
+                        None => true,
+                    };
+                    if enemy.health > 0.0 && closer {
+                        next = Some((entity, enemy.position));
+                    }
+                }
+                true
+            });
+            let (target, to) = match next {
+                Some(next) => next,
+                None => break,
+            };
+
+            damages.send(Damage {
+                target,
+                amount: chain_damage(lightning.damage, jump),
+                kind: DamageKind::Lightning,
+                source: lightning.source,
+                shot: None,
+                crit: false,
+                impulse: Vec2::ZERO,
+            });
+            spawn_arc(&mut commands, &mut pool, from, to);
+            hit.push(target);
+            from = to;
+        }
+    }
+}
+
+/// Draws a jagged line of glowing sprites between two points.
+fn spawn_arc(commands: &mut Commands, pool: &mut Pool<LightningArc>, from: Vec2, to: Vec2) {
+    let mut rng = rand::thread_rng();
+    let normal = (to - from).perp().normalize_or_zero();
+    let jitter = from.distance(to) * 0.1;
+    let mut points = vec![from];
+    for i in 1..ARC_SEGMENTS {
+        let t = i as f32 / ARC_SEGMENTS as f32;
+        points.push(from.lerp(to, t) + normal * rng.gen_range(-jitter..=jitter));
+    }
+    points.push(to);
+
+    for pair in points.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let delta = b - a;
+        pool.spawn(commands, (
+            SpriteBundle {
+                sprite: Sprite {
+                    color: LIGHTNING_GLOW,
+                    custom_size: Some(vec2(delta.length(), ARC_WIDTH)),
+                    ..default()
+                },
+                transform: Transform {
+                    translation: ((a + b) / 2.0).extend(960.0),
+                    rotation: Quat::from_rotation_z(delta.y.atan2(delta.x)),
+                    ..default()
+                },
+                ..default()
+            },
+            LightningArc(Timer::from_seconds(ARC_TIME, TimerMode::Once)),
+        ));
+    }
+}
+
+pub fn tick_arcs(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut pool: ResMut<Pool<LightningArc>>,
+    mut arcs: Query<(Entity, &mut LightningArc, &mut Sprite), Without<Parked>>,
+) {
+    for (entity, mut arc, mut sprite) in arcs.iter_mut() {
+        arc.0.tick(time.delta());
+        sprite.color.set_a(arc.0.percent_left());
+        if arc.0.finished() {
+            pool.release(&mut commands, entity);
+        }
+    }
+}
+
+#[test]
+fn chain_damage_decays() {
+    assert_eq!(chain_damage(100.0, 0), 100.0);
+    assert!((chain_damage(100.0, 2) - 49.0).abs() < 1e-3);
+    assert!(chain_damage(100.0, 3) < chain_damage(100.0, 2));
+}
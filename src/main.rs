@@ -1,35 +1,64 @@
+mod audio;
+mod beam;
 mod bullet;
+mod camera;
+mod console;
+mod damage;
+mod dungeon;
 mod enemy;
+mod feedback;
+mod flow_field;
 mod game;
+mod graphics;
+mod hazard;
+mod input;
+mod inspector;
+mod lightning;
 mod map;
+mod minimap;
+mod particles;
 mod physics_sprite;
 mod pickup;
 mod player;
+mod pool;
 mod prelude;
+mod settings;
+mod tuning;
 mod ui;
 
 use bevy::{
     asset::LoadState,
-    core_pipeline::{
-        bloom::BloomSettings, clear_color::ClearColorConfig,
-    },
+    input::InputSystem,
     math::vec2,
-    render::{
-        camera::{CameraProjection, ScalingMode},
-        primitives::Frustum,
-    },
     sprite::Mesh2dHandle,
 };
 
-use bevy_egui::{EguiPlugin, EguiSettings};
+use bevy_egui::EguiPlugin;
 use iyes_loopless::prelude::*;
 
+use audio::*;
+use beam::*;
 use bullet::*;
+use camera::*;
+use console::*;
+use damage::*;
+use dungeon::*;
 use enemy::*;
+use feedback::*;
+use flow_field::*;
+use game::*;
+use graphics::*;
+use hazard::*;
+use lightning::*;
 use map::*;
+use minimap::*;
+use particles::*;
 use pickup::*;
 use player::*;
+use pool::*;
 use prelude::*;
+use settings::*;
+use tuning::*;
 
 #[derive(Default)]
 struct Handles {
@@ -39,12 +68,16 @@ struct Handles {
     pickup_tex: Handle<Image>,
     pickup_mesh: Mesh2dHandle,
     bullet_mesh: Mesh2dHandle,
+    beam_mesh: Mesh2dHandle,
 
     enemy_tex: Handle<Image>,
     enemy_atlas: Handle<TextureAtlas>,
     enemy_mesh: Mesh2dHandle,
 
     map_tex: Handle<Image>,
+    map_layout: Handle<MapLayout>,
+    particles: Handle<ParticleEffects>,
+    tuning: Handle<Tuning>,
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, Hash, Default)]
@@ -60,42 +93,122 @@ enum GameState {
 
 #[derive(Default, Resource)]
 pub struct Game {
-    player: Player,
+    player_count: usize,
     handles: Handles,
     mouse_world_pos: Vec2,
     mouse_rel_pos: Vec2,
     window_size: Vec2,
-    wave: i32,
+    // play the arena map with endless waves instead of generated floors
+    endless: bool,
+    // dungeon seed for new runs, set from the console to replay a run; random when unset
+    seed: Option<u64>,
     kills: i32,
 }
 
 fn main() {
+    let settings = Settings::load();
     App::new()
-        .insert_resource(Msaa{samples: 1})
-        .init_resource::<Game>()
-        .add_loopless_state(GameState::Init)
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            window: WindowDescriptor { title: "Hello".into(), ..default() },
+        .insert_resource(Msaa { samples: settings.graphics.msaa })
+        .insert_resource(Game {
+            player_count: 1,
             ..default()
-        }))
+        })
+        .init_resource::<SettingsMenu>()
+        .init_resource::<FlowField>()
+        .init_resource::<HazardClock>()
+        .init_resource::<MapView>()
+        .init_resource::<DamageNumbers>()
+        .init_resource::<Tuning>()
+        .init_resource::<Pool<Bullet>>()
+        .init_resource::<Pool<LightningArc>>()
+        .init_resource::<Pool<ExplosionFlash>>()
+        .init_resource::<Pool<Particle>>()
+        .add_loopless_state(GameState::Init)
+        .add_plugins(
+            DefaultPlugins
+                .set(WindowPlugin {
+                    window: settings.graphics.window("Hello"),
+                    ..default()
+                })
+                // so the tuning file can be edited while the game runs
+                .set(AssetPlugin {
+                    watch_for_changes: true,
+                    ..default()
+                }),
+        )
         .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0))
+        .add_plugin(RapierDebugRenderPlugin {
+            enabled: false,
+            ..default()
+        })
         .add_plugin(EguiPlugin)
+        .add_plugin(SoundPlugin)
+        .add_plugin(ConsolePlugin)
+        .insert_resource(settings)
+        .add_asset::<MapLayout>()
+        .init_asset_loader::<MapLayoutLoader>()
+        .add_asset::<ParticleEffects>()
+        .init_asset_loader::<ParticleEffectsLoader>()
+        .add_asset::<Tuning>()
+        .init_asset_loader::<TuningLoader>()
+        .add_event::<ScreenShake>()
+        .add_event::<Damage>()
+        .add_event::<DamageDealt>()
+        .add_event::<Explosion>()
+        .add_event::<ChainLightning>()
+        .add_event::<EmitParticles>()
         .add_startup_system(setup)
+        .add_system_to_stage(CoreStage::PreUpdate, input::assign_gamepads.after(InputSystem))
+        .add_system_to_stage(CoreStage::PreUpdate, input::update_actions.after(input::assign_gamepads))
         .add_system(wait_for_assets.run_in_state(GameState::Init))
+        .add_system(input::capture_binding)
         .add_system(ui::draw_hud)
         .add_system(ui::draw_main_menu.run_in_state(GameState::Menu))
         .add_system(ui::draw_game_over.run_in_state(GameState::GameOver))
         .add_system(ui::draw_pause_menu.run_in_state(GameState::Paused))
+        .add_system(ui::draw_settings)
+        .add_system(inspector::show_colliders)
+        .add_system(inspector::draw_inspector)
+        .add_system_to_stage(CoreStage::PostUpdate, inspector::forget_parked)
+        .add_system(pool::apply_caps)
+        .add_system(graphics::apply)
+        .add_system(tuning::reload)
+        .add_system(tuning::apply.after(tuning::reload))
+        .add_system_to_stage(CoreStage::Last, graphics::limit_fps)
         .add_system(reset.run_in_state(GameState::Reset))
+        .add_system(camera::tick.run_in_state(GameState::Gameplay))
         .add_system_set(
             ConditionSet::new()
                 .run_in_state(GameState::Gameplay)
                 .with_system(player::tick_cursor)
                 .with_system(player::tick)
+                .with_system(inspector::pick)
                 .with_system(pickup::tick)
+                .with_system(flow_field::tick)
                 .with_system(enemy::tick)
                 .with_system(bullet::tick)
+                .with_system(beam::tick)
+                .with_system(hazard::tick_hazards)
+                .with_system(hazard::tick_props)
+                .with_system(hazard::explode)
+                .with_system(lightning::chain)
+                .with_system(lightning::tick_arcs)
+                .with_system(damage::apply)
+                .with_system(feedback::on_damage)
+                .with_system(feedback::tick_flashes)
+                .with_system(feedback::draw_numbers)
+                .with_system(minimap::draw_map)
+                .with_system(minimap::draw_arrows)
                 .with_system(game::spawn_waves)
+                .with_system(game::tick_spawn_warnings)
+                .with_system(dungeon::tick_rooms)
+                .with_system(dungeon::tick_stairs)
+                .with_system(pool::tick::<Bullet>)
+                .with_system(pool::tick::<LightningArc>)
+                .with_system(pool::tick::<ExplosionFlash>)
+                .with_system(pool::tick::<Particle>)
+                .with_system(particles::emit)
+                .with_system(particles::tick_particles)
                 .into(),
         )
         .run();
@@ -110,10 +223,18 @@ fn wait_for_assets(
 ) {
     println!("Waiting for assets");
 
+    let layout_state = asset_server.get_load_state(&game.handles.map_layout);
+    // a broken particles file just means no particles, so it doesn't hold up the game either
+    let particles_state = asset_server.get_load_state(&game.handles.particles);
+    // nor does a broken tuning file, which leaves the defaults
+    let tuning_state = asset_server.get_load_state(&game.handles.tuning);
     if LoadState::Loaded == asset_server.get_load_state(&game.handles.enemy_tex)
         && LoadState::Loaded == asset_server.get_load_state(&game.handles.player_tex)
         && LoadState::Loaded == asset_server.get_load_state(&game.handles.map_tex)
         && LoadState::Loaded == asset_server.get_load_state(&game.handles.pickup_tex)
+        && (LoadState::Loaded == layout_state || LoadState::Failed == layout_state)
+        && (LoadState::Loaded == particles_state || LoadState::Failed == particles_state)
+        && (LoadState::Loaded == tuning_state || LoadState::Failed == tuning_state)
     {
         println!("Textures loaded. Building texture atlases");
         {
@@ -131,115 +252,77 @@ fn wait_for_assets(
         }
 
         println!("Atlas Building Complete");
+
         commands.insert_resource(NextState(GameState::Menu));
     }
 }
 
-fn reset(mut commands: Commands, mut game: ResMut<Game>, enemies: Query<(Entity, With<Enemy>)>) {
-    game.player = Player {
-        id: game.player.id,
-        ..default()
-    };
-
-    game.wave = 0;
+fn reset(
+    mut commands: Commands,
+    mut game: ResMut<Game>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    layouts: Res<Assets<MapLayout>>,
+    tuning: Res<Tuning>,
+    players: Query<Entity, Or<(With<Player>, With<Cursor>)>>,
+    floor: Query<Entity, FloorEntities>,
+) {
     game.kills = 0;
-    game.player.score = 0;
 
-    //enemies
-    for e in enemies.iter() {
-        commands.entity(e.0).despawn();
+    for e in players.iter() {
+        commands.entity(e).despawn();
     }
 
-    commands.insert_resource(NextState(GameState::Gameplay));
-}
-
-fn make_camera() -> Camera2dBundle {
-    let far = 1000.0;
-    // we want 0 to be "closest" and +far to be "farthest" in 2d, so we offset
-    // the camera's translation by far and use a right handed coordinate system
-    let projection = OrthographicProjection {
-        far,
-        scaling_mode: ScalingMode::FixedVertical(MAP_DIMS.y),
-        ..Default::default()
+    //map
+    let (dungeon, arena) = if game.endless {
+        // a broken map file shouldn't stop the game from starting
+        let layout = match layouts.get(&game.handles.map_layout) {
+            Some(layout) => layout.clone(),
+            None => {
+                println!("Map failed to load, falling back to the default arena");
+                MapLayout::default()
+            }
+        };
+        Dungeon::endless(&layout)
+    } else {
+        Dungeon::generate(game.seed.unwrap_or_else(rand::random), 1)
     };
-    let transform = Transform::from_xyz(0.0, 0.0, far - 0.1);
-    let view_projection = projection.get_projection_matrix() * transform.compute_matrix().inverse();
-    let frustum = Frustum::from_view_projection(
-        &view_projection,
-        &transform.translation,
-        &transform.back(),
-        projection.far(),
+    let start = dungeon.start_position(&arena);
+    spawn_floor(&mut commands, dungeon, arena, game.handles.map_tex.clone(), &floor);
+
+    //players
+    spawn_players(
+        &mut commands,
+        game.player_count,
+        start,
+        game.handles.player_mesh.clone(),
+        &mut materials,
+        &tuning,
     );
-    Camera2dBundle {
-        projection,
-        frustum,
-        transform,
-        camera_2d: Camera2d{ clear_color: ClearColorConfig::Custom(Color::BLACK)},
-        camera: Camera {
-            hdr: true,
-            ..default()
-        },
-        ..default()
-    }
+
+    commands.insert_resource(NextState(GameState::Gameplay));
 }
 
 fn setup(
     mut commands: Commands,
     mut game: ResMut<Game>,
-    mut egui_settings: ResMut<EguiSettings>,
     asset_server: Res<AssetServer>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
-    // gui
-    egui_settings.as_mut().scale_factor = 2.0;
-
-    // camera
-    commands.spawn((
-        make_camera(),
-        BloomSettings {
-            intensity: 0.3,
-            threshold: 0.6,
-            knee: 0.1,
-            ..default()
-        },
-    ));
+    // camera; bloom, MSAA and the UI scale come from the graphics settings
+    commands.spawn((make_camera(), CameraRig::default()));
 
     // load assets
     game.handles.map_tex = asset_server.load("map.png");
     game.handles.pickup_tex = asset_server.load("pickup.png");
     game.handles.enemy_tex = asset_server.load("creature-sheet.png");
     game.handles.player_tex = asset_server.load("player.png");
+    game.handles.map_layout = asset_server.load("maps/arena.map.ron");
+    game.handles.particles = asset_server.load("effects.particles.ron");
+    game.handles.tuning = asset_server.load("game.tuning.ron");
 
     game.handles.player_mesh = meshes.add(make_mesh()).into();
     game.handles.enemy_mesh = meshes.add(shape::Circle::new(10.0).into()).into();
     game.handles.pickup_mesh = meshes.add(shape::Box::new(10.0, 10.0, 10.0).into()).into();
     game.handles.bullet_mesh = meshes.add(shape::Circle::new(10.0).into()).into();
-
-    // map
-    commands.spawn(MapBundle::new(Vec2::ZERO, game.handles.map_tex.clone()));
-
-    //player
-    game.player.id = Some(
-        commands
-            .spawn(PlayerBundle::new(
-                materials.add(ColorMaterial {
-                    color: Color::hsla(130.0, 1.0, 0.5, 1.0),
-                    texture: None,
-                }),
-                game.handles.player_mesh.clone(),
-            ))
-            .id(),
-    );
-
-    commands.spawn(CursorBundle {
-        sprite: SpriteBundle {
-            sprite: Sprite {
-                custom_size: Some(vec2(10.0, 10.0)),
-                ..default()
-            },
-            ..default()
-        },
-        ..default()
-    });
+    game.handles.beam_mesh = meshes.add(make_beam_mesh()).into();
 }
@@ -1,4 +1,9 @@
-use bevy_rapier2d::na::clamp;
+use bevy::{
+    asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+    reflect::TypeUuid,
+};
+use bevy_rapier2d::{na::Isometry2, parry::query::contact};
+use serde::Deserialize;
 
 use crate::*;
 
@@ -13,23 +18,14 @@ pub struct MapBundle {
     sprite: SpriteBundle,
 }
 
-pub const MAP_DIMS: Vec2 = vec2(1200.0, 800.0);
-
-pub fn clamp_position(pos: &Vec2) -> Vec2 {
-    return vec2(
-        clamp(pos.x, -MAP_DIMS.x / 2.2, MAP_DIMS.x / 2.2),
-        clamp(pos.y, -MAP_DIMS.y / 2.5, MAP_DIMS.y / 2.3),
-    );
-}
-
 impl MapBundle {
-    pub fn new(pos: Vec2, tex: Handle<Image>) -> Self {
+    pub fn new(pos: Vec2, dims: Vec2, tex: Handle<Image>) -> Self {
         return Self {
             map: Map { ..default() },
             sprite: SpriteBundle {
                 texture: tex,
                 sprite: Sprite {
-                    custom_size: Some(MAP_DIMS),
+                    custom_size: Some(dims),
                     color: Color::hsla(0.0, 0.0, 0.0, 0.0),
                     ..default()
                 },
@@ -43,3 +39,409 @@ impl MapBundle {
         };
     }
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tile {
+    Floor,
+    Wall,
+    Spikes,
+    Lava,
+    Mud,
+}
+
+impl Tile {
+    fn from_char(c: char) -> Option<Tile> {
+        match c {
+            '.' => Some(Tile::Floor),
+            '#' => Some(Tile::Wall),
+            '^' => Some(Tile::Spikes),
+            '~' => Some(Tile::Lava),
+            ',' => Some(Tile::Mud),
+            // props and spawn points stand on plain floor
+            'S' => Some(Tile::Floor),
+            c if PropKind::from_char(c).is_some() => Some(Tile::Floor),
+            _ => None,
+        }
+    }
+
+    pub fn is_walkable(&self) -> bool {
+        return *self != Tile::Wall;
+    }
+
+    pub fn damage_per_second(&self) -> f32 {
+        match self {
+            Tile::Spikes => 10.0,
+            Tile::Lava => 40.0,
+            _ => 0.0,
+        }
+    }
+
+    /// Multiplier on the speed of anything walking over the tile.
+    pub fn speed_scale(&self) -> f32 {
+        match self {
+            Tile::Mud => 0.5,
+            _ => 1.0,
+        }
+    }
+}
+
+/// Arena layout as written in `assets/maps/*.map.ron`. Each row is a string,
+/// top row first, with `#` for walls and `.` for floor. `^` spikes, `~` lava and
+/// `,` mud are hazards; `B` barrels and `C` crates are props standing on floor. `S` marks
+/// a spawn point: if a map has any, enemies only ever appear at those.
+#[derive(Deserialize, TypeUuid, Clone)]
+#[uuid = "5b7c1a6e-2f0e-4a8e-9a43-6f3d0c1b7e21"]
+pub struct MapLayout {
+    pub tile_size: f32,
+    pub rows: Vec<String>,
+}
+
+impl Default for MapLayout {
+    // the original open arena: a 1200x800 box with nothing in it
+    fn default() -> Self {
+        let (width, height) = (24, 16);
+        let mut rows = Vec::new();
+        for y in 0..height {
+            if y == 0 || y == height - 1 {
+                rows.push("#".repeat(width));
+            } else {
+                rows.push(format!("#{}#", ".".repeat(width - 2)));
+            }
+        }
+        return MapLayout {
+            tile_size: 50.0,
+            rows,
+        };
+    }
+}
+
+impl MapLayout {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.tile_size > 0.0) {
+            return Err(format!("tile_size must be positive, got {}", self.tile_size));
+        }
+        let width = match self.rows.first() {
+            Some(row) if !row.is_empty() => row.chars().count(),
+            _ => return Err("map has no rows".into()),
+        };
+        for (y, row) in self.rows.iter().enumerate() {
+            if row.chars().count() != width {
+                return Err(format!(
+                    "row {} is {} tiles wide, expected {}",
+                    y,
+                    row.chars().count(),
+                    width
+                ));
+            }
+            if let Some((x, c)) = row.chars().enumerate().find(|(_, c)| Tile::from_char(*c).is_none()) {
+                return Err(format!("unknown tile '{}' at row {}, column {}", c, y, x));
+            }
+        }
+        if !self.rows.iter().any(|row| row.contains('.') || row.contains('S')) {
+            return Err("map has no floor tiles or spawn points".into());
+        }
+        // anything walkable on the edge would let players and enemies wander off the map
+        let height = self.rows.len();
+        for (y, row) in self.rows.iter().enumerate() {
+            let edge = row
+                .chars()
+                .enumerate()
+                .find(|&(x, c)| (y == 0 || y == height - 1 || x == 0 || x == width - 1) && c != '#');
+            if let Some((x, c)) = edge {
+                return Err(format!("map isn't enclosed by walls, '{}' at row {}, column {}", c, y, x));
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[derive(Default)]
+pub struct MapLayoutLoader;
+
+impl AssetLoader for MapLayoutLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let layout: MapLayout = ron::de::from_bytes(bytes)?;
+            layout
+                .validate()
+                .map_err(|e| bevy::asset::Error::msg(format!("{}: {}", load_context.path().display(), e)))?;
+            load_context.set_default_asset(LoadedAsset::new(layout));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["map.ron"]
+    }
+}
+
+/// The tile grid of the current arena, centred on the origin.
+#[derive(Resource, Clone)]
+pub struct Arena {
+    pub tile_size: f32,
+    pub width: usize,
+    pub height: usize,
+    tiles: Vec<Tile>,
+    pub props: Vec<(PropKind, (i32, i32))>,
+    pub spawn_points: Vec<(i32, i32)>,
+}
+
+impl Arena {
+    pub fn from_layout(layout: &MapLayout) -> Arena {
+        let width = layout.rows.first().map(|r| r.chars().count()).unwrap_or(0);
+        let tiles = layout
+            .rows
+            .iter()
+            .flat_map(|row| row.chars().map(|c| Tile::from_char(c).unwrap_or(Tile::Wall)))
+            .collect();
+        let props = layout
+            .rows
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.chars()
+                    .enumerate()
+                    .filter_map(move |(x, c)| PropKind::from_char(c).map(|kind| (kind, (x as i32, y as i32))))
+            })
+            .collect();
+        let spawn_points = layout
+            .rows
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.chars()
+                    .enumerate()
+                    .filter(|&(_, c)| c == 'S')
+                    .map(move |(x, _)| (x as i32, y as i32))
+            })
+            .collect();
+        return Arena {
+            tile_size: layout.tile_size,
+            width,
+            height: layout.rows.len(),
+            tiles,
+            props,
+            spawn_points,
+        };
+    }
+
+    pub fn dims(&self) -> Vec2 {
+        return vec2(self.width as f32, self.height as f32) * self.tile_size;
+    }
+
+    /// Anything outside the grid counts as wall.
+    pub fn tile(&self, x: i32, y: i32) -> Tile {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return Tile::Wall;
+        }
+        return self.tiles[y as usize * self.width + x as usize];
+    }
+
+    /// Grid cell containing `pos`; row 0 is the top of the map, like in the layout file.
+    pub fn cell_at(&self, pos: Vec2) -> (i32, i32) {
+        let local = (pos + self.dims() / 2.0) / self.tile_size;
+        return (local.x.floor() as i32, self.height as i32 - 1 - local.y.floor() as i32);
+    }
+
+    pub fn cell_center(&self, x: i32, y: i32) -> Vec2 {
+        let local = vec2(x as f32 + 0.5, (self.height as i32 - 1 - y) as f32 + 0.5);
+        return local * self.tile_size - self.dims() / 2.0;
+    }
+
+    pub fn set_tile(&mut self, x: i32, y: i32, tile: Tile) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            self.tiles[y as usize * self.width + x as usize] = tile;
+        }
+    }
+
+    pub fn tile_at(&self, pos: Vec2) -> Tile {
+        let (x, y) = self.cell_at(pos);
+        return self.tile(x, y);
+    }
+
+    pub fn is_walkable(&self, pos: Vec2) -> bool {
+        return self.tile_at(pos).is_walkable();
+    }
+
+    pub fn clamp_position(&self, pos: &Vec2) -> Vec2 {
+        let half = self.dims() / 2.0;
+        return pos.clamp(-half, half);
+    }
+}
+
+#[derive(Component, Default)]
+pub struct Wall;
+
+/// Marks the portals enemies come out of on maps that define spawn points.
+#[derive(Component, Default)]
+pub struct SpawnPoint;
+
+#[derive(Bundle, Default)]
+pub struct WallBundle {
+    wall: Wall,
+    collider: Collider,
+
+    #[bundle]
+    sprite: SpriteBundle,
+}
+
+impl WallBundle {
+    pub fn new(pos: Vec2, size: f32) -> Self {
+        return WallBundle::with_color(pos, size, Color::rgb(0.2, 0.2, 0.25));
+    }
+
+    pub fn door(pos: Vec2, size: f32) -> Self {
+        return WallBundle::with_color(pos, size, Color::rgb(0.6, 0.15, 0.1));
+    }
+
+    fn with_color(pos: Vec2, size: f32, color: Color) -> Self {
+        return WallBundle {
+            wall: Wall,
+            collider: Collider::cuboid(size / 2.0, size / 2.0),
+            sprite: SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(Vec2::splat(size)),
+                    ..default()
+                },
+                transform: Transform::from_translation(pos.extend(50.0)),
+                ..default()
+            },
+        };
+    }
+}
+
+pub fn spawn_arena(commands: &mut Commands, arena: &Arena, tex: Handle<Image>) {
+    commands.spawn(MapBundle::new(Vec2::ZERO, arena.dims(), tex));
+    for y in 0..arena.height as i32 {
+        for x in 0..arena.width as i32 {
+            // solid rock nobody can reach doesn't need a collider
+            let touches_floor = (-1..=1).any(|dy| (-1..=1).any(|dx| arena.tile(x + dx, y + dy).is_walkable()));
+            if arena.tile(x, y) == Tile::Wall && touches_floor {
+                commands.spawn(WallBundle::new(arena.cell_center(x, y), arena.tile_size));
+            }
+            if let Some(hazard) = hazard_sprite(arena.tile(x, y), arena.cell_center(x, y), arena.tile_size) {
+                commands.spawn(hazard);
+            }
+        }
+    }
+    for &(x, y) in arena.spawn_points.iter() {
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: Color::rgba(0.6, 0.2, 0.9, 0.5),
+                    custom_size: Some(Vec2::splat(arena.tile_size * 0.9)),
+                    ..default()
+                },
+                transform: Transform::from_translation(arena.cell_center(x, y).extend(20.0)),
+                ..default()
+            },
+            SpawnPoint,
+        ));
+    }
+    for &(kind, (x, y)) in arena.props.iter() {
+        commands.spawn(PropBundle::new(kind, arena.cell_center(x, y), arena.tile_size * 0.8));
+    }
+}
+
+/// Moves a collider from `from` towards `to`, sliding along walls instead of stopping dead.
+/// Walls are the only solid (non-sensor) colliders, so anything else is ignored.
+pub fn move_and_slide(rapier_ctx: &RapierContext, collider: &Collider, from: Vec2, to: Vec2) -> Vec2 {
+    let filter = QueryFilter::default().exclude_sensors();
+    let blocked = |pos: Vec2| rapier_ctx.intersection_with_shape(pos, 0.0, collider, filter).is_some();
+    let slide_x = vec2(to.x, from.y);
+    let slide_y = vec2(from.x, to.y);
+
+    // something that ended up inside a wall can only move in ways that get it further out
+    let start_depth = wall_depth(rapier_ctx, collider, from);
+    if start_depth > 0.0 {
+        return [to, slide_x, slide_y]
+            .into_iter()
+            .find(|&pos| wall_depth(rapier_ctx, collider, pos) < start_depth)
+            .unwrap_or(from);
+    }
+
+    if !blocked(to) {
+        return to;
+    }
+    if !blocked(slide_x) {
+        return slide_x;
+    }
+    if !blocked(slide_y) {
+        return slide_y;
+    }
+    return from;
+}
+
+/// How far a collider at `pos` sinks into the walls, summed over every wall it overlaps.
+fn wall_depth(rapier_ctx: &RapierContext, collider: &Collider, pos: Vec2) -> f32 {
+    // rapier works in meters, the colliders we're handed are in pixels
+    let scale = rapier_ctx.physics_scale();
+    let mut shape = collider.clone();
+    shape.set_scale(collider.scale() / scale, 20);
+    let shape_pos = Isometry2::translation(pos.x / scale, pos.y / scale);
+
+    let mut depth = 0.0;
+    let filter = QueryFilter::default().exclude_sensors();
+    rapier_ctx.intersections_with_shape(pos, 0.0, collider, filter, |entity| {
+        let wall = rapier_ctx.entity2collider().get(&entity).and_then(|&h| rapier_ctx.colliders.get(h));
+        if let Some(wall) = wall {
+            if let Ok(Some(contact)) = contact(&shape_pos, &*shape.raw, wall.position(), wall.shape(), 0.0) {
+                depth += (-contact.dist).max(0.0) * scale;
+            }
+        }
+        return true;
+    });
+    return depth;
+}
+
+#[cfg(test)]
+fn test_layout(rows: &[&str]) -> MapLayout {
+    return MapLayout {
+        tile_size: 10.0,
+        rows: rows.iter().map(|r| r.to_string()).collect(),
+    };
+}
+
+#[test]
+fn well_formed_layouts_pass() {
+    assert_eq!(MapLayout::default().validate(), Ok(()));
+    assert_eq!(test_layout(&["#####", "#S^~#", "#.BC#", "#####"]).validate(), Ok(()));
+    // a map that's nothing but spawn points still has somewhere to stand
+    assert_eq!(test_layout(&["###", "#S#", "###"]).validate(), Ok(()));
+
+    let text = std::fs::read_to_string("assets/maps/arena.map.ron").unwrap();
+    let layout: MapLayout = ron::from_str(&text).unwrap();
+    assert_eq!(layout.validate(), Ok(()));
+}
+
+#[test]
+fn ragged_rows_are_rejected() {
+    let err = test_layout(&["#####", "#..#", "#####"]).validate().unwrap_err();
+    assert_eq!(err, "row 1 is 4 tiles wide, expected 5");
+}
+
+#[test]
+fn unknown_tiles_are_rejected() {
+    let err = test_layout(&["#####", "#.?.#", "#####"]).validate().unwrap_err();
+    assert_eq!(err, "unknown tile '?' at row 1, column 2");
+}
+
+#[test]
+fn maps_without_floor_are_rejected() {
+    let err = test_layout(&["####", "#^~#", "####"]).validate().unwrap_err();
+    assert_eq!(err, "map has no floor tiles or spawn points");
+    assert!(test_layout(&[]).validate().is_err());
+}
+
+#[test]
+fn open_edges_are_rejected() {
+    let err = test_layout(&["#####", "#...#", "##.##"]).validate().unwrap_err();
+    assert_eq!(err, "map isn't enclosed by walls, '.' at row 2, column 2");
+    let err = test_layout(&["#####", "....#", "#####"]).validate().unwrap_err();
+    assert_eq!(err, "map isn't enclosed by walls, '.' at row 1, column 0");
+}
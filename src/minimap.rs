@@ -0,0 +1,227 @@
+use bevy_egui::{
+    egui::{self, Color32},
+    EguiContext, EguiSettings,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+use input::{Action, ActionState};
+use ui::{hud_style, to_egui_color, world_to_ui};
+
+const ARROW_MARGIN: f32 = 12.0;
+const ARROW_SIZE: f32 = 8.0;
+// only the closest few enemies get an arrow so the screen edges don't fill up
+const MAX_ENEMY_ARROWS: usize = 8;
+const MAX_ZOOM: f32 = 4.0;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MinimapConfig {
+    pub show_minimap: bool,
+    pub show_arrows: bool,
+    // length of the minimap's longer side, in ui points
+    pub size: f32,
+}
+
+impl Default for MinimapConfig {
+    fn default() -> Self {
+        MinimapConfig {
+            show_minimap: true,
+            show_arrows: true,
+            size: 120.0,
+        }
+    }
+}
+
+/// The fullscreen map, opened with [`Action::Map`].
+#[derive(Resource)]
+pub struct MapView {
+    pub fullscreen: bool,
+    pub zoom: f32,
+}
+
+impl Default for MapView {
+    fn default() -> Self {
+        MapView {
+            fullscreen: false,
+            zoom: 1.0,
+        }
+    }
+}
+
+/// What the map shows, gathered once for either view.
+struct Markers {
+    players: Vec<(Vec2, Color)>,
+    enemies: Vec<Vec2>,
+    pickups: Vec<(Vec2, Color)>,
+    exits: Vec<Vec2>,
+}
+
+/// Draws the arena into `rect`, centred on the world position `center`, at `scale` points per world unit.
+fn paint_map(painter: &egui::Painter, rect: egui::Rect, center: Vec2, scale: f32, arena: &Arena, markers: &Markers) {
+    let painter = painter.with_clip_rect(rect);
+    let to_screen = |pos: Vec2| {
+        let p = (pos - center) * scale;
+        rect.center() + egui::vec2(p.x, -p.y)
+    };
+    painter.rect_filled(rect, 2.0, Color32::from_black_alpha(160));
+
+    // one rect per horizontal run of floor keeps the shape count down on big floors
+    let half = arena.tile_size / 2.0;
+    for y in 0..arena.height as i32 {
+        let mut x = 0;
+        while x < arena.width as i32 {
+            if !arena.tile(x, y).is_walkable() {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x < arena.width as i32 && arena.tile(x, y).is_walkable() {
+                x += 1;
+            }
+            let a = arena.cell_center(start, y) + vec2(-half, half);
+            let b = arena.cell_center(x - 1, y) + vec2(half, -half);
+            painter.rect_filled(egui::Rect::from_two_pos(to_screen(a), to_screen(b)), 0.0, Color32::from_gray(70));
+        }
+    }
+    let bounds = arena.dims() / 2.0;
+    painter.rect_stroke(
+        egui::Rect::from_two_pos(to_screen(-bounds), to_screen(bounds)),
+        0.0,
+        egui::Stroke::new(1.0, Color32::GRAY),
+    );
+
+    for &pos in markers.exits.iter() {
+        let exit = egui::Rect::from_center_size(to_screen(pos), egui::vec2(6.0, 6.0));
+        painter.rect_filled(exit, 0.0, Color32::from_rgb(100, 230, 255));
+    }
+    for &(pos, color) in markers.pickups.iter() {
+        painter.circle_filled(to_screen(pos), 2.5, to_egui_color(color));
+    }
+    for &pos in markers.enemies.iter() {
+        painter.circle_filled(to_screen(pos), 2.0, Color32::RED);
+    }
+    for &(pos, color) in markers.players.iter() {
+        painter.circle_filled(to_screen(pos), 3.5, to_egui_color(color));
+    }
+}
+
+pub fn draw_map(
+    mut egui_context: ResMut<EguiContext>,
+    settings: Res<Settings>,
+    mut view: ResMut<MapView>,
+    arena: Res<Arena>,
+    players: Query<(&Player, &ActionState)>,
+    enemies: Query<&Enemy>,
+    pickups: Query<(&Transform, &Pickup)>,
+    exits: Query<&Transform, With<Stairs>>,
+) {
+    if players.iter().any(|(_, actions)| actions.just_pressed(Action::Map)) {
+        view.fullscreen = !view.fullscreen;
+    }
+
+    let markers = Markers {
+        players: players.iter().map(|(p, _)| (p.position, p.color)).collect(),
+        enemies: enemies.iter().map(|e| e.position).collect(),
+        pickups: pickups
+            .iter()
+            .map(|(t, p)| (t.translation.truncate(), Pickup::get_color(p.kind.clone())))
+            .collect(),
+        exits: exits.iter().map(|t| t.translation.truncate()).collect(),
+    };
+    let dims = arena.dims();
+
+    if view.fullscreen {
+        egui::Area::new("map view")
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(egui_context.ctx_mut(), |ui| {
+                hud_style(ui);
+                ui.add(egui::Slider::new(&mut view.zoom, 1.0..=MAX_ZOOM).text("Zoom"));
+                let size = ui.ctx().input().screen_rect().size() * 0.8;
+                let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+                if response.hovered() {
+                    let scroll = ui.ctx().input().scroll_delta.y;
+                    view.zoom = (view.zoom * (1.0 + scroll * 0.002)).clamp(1.0, MAX_ZOOM);
+                }
+
+                // zoomed in, follow the players; zoomed all the way out, show the whole floor
+                let center = if view.zoom > 1.0 && !markers.players.is_empty() {
+                    markers.players.iter().map(|(pos, _)| *pos).sum::<Vec2>() / markers.players.len() as f32
+                } else {
+                    Vec2::ZERO
+                };
+                let fit = (size.x / dims.x).min(size.y / dims.y);
+                paint_map(&painter, response.rect, center, fit * view.zoom, &arena, &markers);
+            });
+    } else if settings.minimap.show_minimap {
+        // player 2's panel has the top right corner in co-op
+        let offset = if players.iter().count() > 1 { 80.0 } else { 10.0 };
+        egui::Area::new("minimap")
+            .anchor(egui::Align2::RIGHT_TOP, [-10.0, offset])
+            .show(egui_context.ctx_mut(), |ui| {
+                let scale = settings.minimap.size / dims.x.max(dims.y);
+                let (response, painter) = ui.allocate_painter(egui::vec2(dims.x, dims.y) * scale, egui::Sense::hover());
+                paint_map(&painter, response.rect, Vec2::ZERO, scale, &arena, &markers);
+            });
+    }
+}
+
+/// Draws an arrow on the edge of `screen` pointing at `target`, unless it's on screen already.
+fn paint_arrow(painter: &egui::Painter, screen: egui::Rect, target: egui::Pos2, color: Color32) {
+    let inner = screen.shrink(ARROW_MARGIN);
+    if inner.contains(target) {
+        return;
+    }
+    let center = inner.center();
+    let dir = (target - center).normalized();
+    // walk out from the centre until we hit the edge
+    let to_edge = |half: f32, d: f32| if d == 0.0 { f32::INFINITY } else { half / d.abs() };
+    let tip = center + dir * to_edge(inner.width() / 2.0, dir.x).min(to_edge(inner.height() / 2.0, dir.y));
+    let back = tip - dir * ARROW_SIZE;
+    let side = egui::vec2(-dir.y, dir.x) * ARROW_SIZE * 0.6;
+    painter.add(egui::Shape::convex_polygon(vec![tip, back + side, back - side], color, egui::Stroke::NONE));
+}
+
+pub fn draw_arrows(
+    mut egui_context: ResMut<EguiContext>,
+    egui_settings: Res<EguiSettings>,
+    settings: Res<Settings>,
+    view: Res<MapView>,
+    windows: Res<Windows>,
+    cameras: Query<(&Camera, &GlobalTransform), With<CameraRig>>,
+    enemies: Query<&Enemy>,
+    pickups: Query<(&Transform, &Pickup)>,
+) {
+    if !settings.minimap.show_arrows || view.fullscreen {
+        return;
+    }
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let (camera, camera_transform) = match cameras.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let ui_scale = egui_settings.scale_factor as f32;
+    let to_ui = |pos: Vec2| world_to_ui(window, camera, camera_transform, ui_scale, pos);
+
+    let ctx = egui_context.ctx_mut();
+    let screen = ctx.input().screen_rect();
+    let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Background, egui::Id::new("offscreen arrows")));
+
+    let mut offscreen: Vec<egui::Pos2> = enemies
+        .iter()
+        .filter_map(|e| to_ui(e.position))
+        .filter(|&p| !screen.contains(p))
+        .collect();
+    offscreen.sort_by(|a, b| a.distance(screen.center()).total_cmp(&b.distance(screen.center())));
+    for &pos in offscreen.iter().take(MAX_ENEMY_ARROWS) {
+        paint_arrow(&painter, screen, pos, Color32::RED);
+    }
+    for (transform, pickup) in pickups.iter() {
+        if let Some(pos) = to_ui(transform.translation.truncate()) {
+            paint_arrow(&painter, screen, pos, to_egui_color(Pickup::get_color(pickup.kind.clone())));
+        }
+    }
+}
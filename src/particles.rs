@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+
+use bevy::{
+    asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset},
+    reflect::TypeUuid,
+};
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::*;
+
+// particles draw above the arena and everything on it, but under explosion flashes
+const PARTICLE_Z: f32 = 900.0;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub enum EmitMode {
+    /// Everything at once.
+    Burst(u32),
+    /// `rate` particles per second for `duration` seconds.
+    Continuous { rate: f32, duration: f32 },
+}
+
+/// One effect as written in `assets/effects.particles.ron`. Ranges are `(min, max)` and
+/// picked from at random for every particle.
+#[derive(Deserialize, Clone, Debug)]
+pub struct EmitterDef {
+    pub mode: EmitMode,
+    pub lifetime: (f32, f32),
+    pub speed: (f32, f32),
+    // width of the cone particles fly out in, in degrees around the emit direction
+    pub spread: f32,
+    #[serde(default)]
+    pub drag: f32,
+    // at the start and end of a particle's life
+    pub size: (f32, f32),
+    // spread evenly over a particle's life; above 1.0 glows through the bloom
+    pub colors: Vec<(f32, f32, f32, f32)>,
+}
+
+impl EmitterDef {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.lifetime.0 > 0.0 && self.lifetime.0 <= self.lifetime.1) {
+            return Err(format!("lifetime must be positive with min <= max, got {:?}", self.lifetime));
+        }
+        if !(self.speed.0 >= 0.0 && self.speed.0 <= self.speed.1) {
+            return Err(format!("speed must be non-negative with min <= max, got {:?}", self.speed));
+        }
+        if !(0.0..=360.0).contains(&self.spread) {
+            return Err(format!("spread must be between 0 and 360 degrees, got {}", self.spread));
+        }
+        if !(self.drag >= 0.0) {
+            return Err(format!("drag can't be negative, got {}", self.drag));
+        }
+        // size is lerped from start to end, so it can grow or shrink, just never go negative
+        if !(self.size.0 >= 0.0 && self.size.1 >= 0.0) {
+            return Err(format!("size can't be negative, got {:?}", self.size));
+        }
+        if self.colors.is_empty() {
+            return Err("colors must have at least one entry".into());
+        }
+        if let EmitMode::Continuous { rate, duration } = self.mode {
+            if !(rate > 0.0 && duration > 0.0) {
+                return Err(format!("continuous rate and duration must be positive, got {} and {}", rate, duration));
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[derive(Deserialize, TypeUuid, Clone, Default)]
+#[uuid = "0f3c8e2a-6d41-4b7e-8f6a-2c9d5e1b4a73"]
+pub struct ParticleEffects {
+    pub effects: HashMap<String, EmitterDef>,
+}
+
+#[derive(Default)]
+pub struct ParticleEffectsLoader;
+
+impl AssetLoader for ParticleEffectsLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let effects: ParticleEffects = ron::de::from_bytes(bytes)?;
+            for (name, def) in effects.effects.iter() {
+                def.validate()
+                    .map_err(|e| bevy::asset::Error::msg(format!("{}: effect '{}': {}", load_context.path().display(), name, e)))?;
+            }
+            load_context.set_default_asset(LoadedAsset::new(effects));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["particles.ron"]
+    }
+}
+
+/// Plays the named effect from the particles file. Effects that aren't in the file are
+/// quietly skipped.
+pub struct EmitParticles {
+    pub effect: &'static str,
+    pub position: Vec2,
+    // the middle of the cone; zero for no particular direction
+    pub direction: Vec2,
+}
+
+/// Keeps spawning particles for a continuous effect until it runs out.
+#[derive(Component)]
+pub struct Emitter {
+    def: EmitterDef,
+    direction: Vec2,
+    timer: Timer,
+    // fractional particles carried over between frames
+    owed: f32,
+}
+
+#[derive(Component, Default)]
+pub struct Particle {
+    velocity: Vec2,
+    age: f32,
+    lifetime: f32,
+    drag: f32,
+    size: (f32, f32),
+    colors: Vec<Color>,
+}
+
+/// Where `t` (0 to 1) falls between evenly spaced colours.
+pub fn color_over_life(colors: &[Color], t: f32) -> Color {
+    if colors.len() < 2 {
+        return colors.first().copied().unwrap_or(Color::WHITE);
+    }
+    let x = t.clamp(0.0, 1.0) * (colors.len() - 1) as f32;
+    let i = (x.floor() as usize).min(colors.len() - 2);
+    let f = x - i as f32;
+    let [r0, g0, b0, a0] = colors[i].as_rgba_f32();
+    let [r1, g1, b1, a1] = colors[i + 1].as_rgba_f32();
+    return Color::rgba(lerp(r0, r1, f), lerp(g0, g1, f), lerp(b0, b1, f), lerp(a0, a1, f));
+}
+
+fn spawn_particle(commands: &mut Commands, pool: &mut Pool<Particle>, def: &EmitterDef, position: Vec2, direction: Vec2) {
+    let mut rng = rand::thread_rng();
+    let base = if direction == Vec2::ZERO {
+        rng.gen_range(0.0..std::f32::consts::TAU)
+    } else {
+        direction.y.atan2(direction.x)
+    };
+    let half_spread = def.spread.to_radians() / 2.0;
+    let angle = base + rng.gen_range(-half_spread..=half_spread);
+    let speed = rng.gen_range(def.speed.0..=def.speed.1);
+    let colors: Vec<Color> = def.colors.iter().map(|&(r, g, b, a)| Color::rgba(r, g, b, a)).collect();
+
+    pool.spawn(
+        commands,
+        (
+            SpriteBundle {
+                sprite: Sprite {
+                    color: colors[0],
+                    custom_size: Some(Vec2::splat(def.size.0)),
+                    ..default()
+                },
+                transform: Transform::from_translation(position.extend(PARTICLE_Z)),
+                ..default()
+            },
+            Particle {
+                velocity: vec2(angle.cos(), angle.sin()) * speed,
+                age: 0.0,
+                lifetime: rng.gen_range(def.lifetime.0..=def.lifetime.1),
+                drag: def.drag,
+                size: def.size,
+                colors,
+            },
+        ),
+    );
+}
+
+/// Starts effects asked for this frame and keeps continuous ones going.
+pub fn emit(
+    mut commands: Commands,
+    game: Res<Game>,
+    time: Res<Time>,
+    effects: Res<Assets<ParticleEffects>>,
+    mut pool: ResMut<Pool<Particle>>,
+    mut requests: EventReader<EmitParticles>,
+    mut emitters: Query<(Entity, &mut Emitter, &Transform)>,
+) {
+    if let Some(effects) = effects.get(&game.handles.particles) {
+        for request in requests.iter() {
+            let def = match effects.effects.get(request.effect) {
+                Some(def) => def,
+                None => continue,
+            };
+            match def.mode {
+                EmitMode::Burst(count) => {
+                    for _ in 0..count {
+                        spawn_particle(&mut commands, &mut pool, def, request.position, request.direction);
+                    }
+                }
+                EmitMode::Continuous { duration, .. } => {
+                    commands.spawn((
+                        Emitter {
+                            def: def.clone(),
+                            direction: request.direction,
+                            timer: Timer::from_seconds(duration, TimerMode::Once),
+                            owed: 0.0,
+                        },
+                        TransformBundle::from_transform(Transform::from_translation(request.position.extend(0.0))),
+                    ));
+                }
+            }
+        }
+    }
+
+    for (entity, mut emitter, transform) in emitters.iter_mut() {
+        emitter.timer.tick(time.delta());
+        if let EmitMode::Continuous { rate, .. } = emitter.def.mode {
+            emitter.owed += rate * time.delta_seconds();
+        }
+        while emitter.owed >= 1.0 {
+            emitter.owed -= 1.0;
+            spawn_particle(&mut commands, &mut pool, &emitter.def, transform.translation.truncate(), emitter.direction);
+        }
+        if emitter.timer.finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub fn tick_particles(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut pool: ResMut<Pool<Particle>>,
+    mut particles: Query<(Entity, &mut Particle, &mut Transform, &mut Sprite), Without<Parked>>,
+) {
+    let dt = time.delta_seconds();
+    for (entity, mut particle, mut transform, mut sprite) in particles.iter_mut() {
+        particle.age += dt;
+        if particle.age >= particle.lifetime {
+            pool.release(&mut commands, entity);
+            continue;
+        }
+        let t = particle.age / particle.lifetime;
+        let drag = particle.drag;
+        particle.velocity *= (-drag * dt).exp();
+        transform.translation += (particle.velocity * dt).extend(0.0);
+        sprite.color = color_over_life(&particle.colors, t);
+        sprite.custom_size = Some(Vec2::splat(lerp(particle.size.0, particle.size.1, t)));
+    }
+}
+
+#[test]
+fn colors_blend_over_life() {
+    let colors = [Color::rgba(4.0, 0.0, 0.0, 1.0), Color::rgba(0.0, 0.0, 2.0, 0.0)];
+    assert_eq!(color_over_life(&colors, 0.0).as_rgba_f32(), [4.0, 0.0, 0.0, 1.0]);
+    assert_eq!(color_over_life(&colors, 0.5).as_rgba_f32(), [2.0, 0.0, 1.0, 0.5]);
+    assert_eq!(color_over_life(&colors, 1.0).as_rgba_f32(), [0.0, 0.0, 2.0, 0.0]);
+    assert_eq!(color_over_life(&colors[..1], 0.7).as_rgba_f32(), [4.0, 0.0, 0.0, 1.0]);
+}
+
+#[test]
+fn bundled_effects_are_valid() {
+    let text = std::fs::read_to_string("assets/effects.particles.ron").unwrap();
+    let effects: ParticleEffects = ron::from_str(&text).unwrap();
+    for name in ["muzzle_flash", "impact", "death", "pickup", "level_up"] {
+        let def = effects.effects.get(name).expect(name);
+        assert_eq!(def.validate(), Ok(()), "{}", name);
+    }
+}
+
+#[test]
+fn negative_size_and_drag_are_rejected() {
+    let text = std::fs::read_to_string("assets/effects.particles.ron").unwrap();
+    let effects: ParticleEffects = ron::from_str(&text).unwrap();
+    let mut def = effects.effects["impact"].clone();
+    def.drag = -1.0;
+    assert!(def.validate().unwrap_err().starts_with("drag"));
+    def.drag = 0.0;
+    def.size = (4.0, -1.0);
+    assert!(def.validate().unwrap_err().starts_with("size"));
+}
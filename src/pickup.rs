@@ -2,11 +2,11 @@ use crate::{physics_sprite::PhysicsSpriteBundle, *};
 use bevy::prelude::*;
 use bevy::sprite::Mesh2dHandle;
 use player::Player;
-use rand::Rng;
-use strum::{IntoEnumIterator, EnumCount};
+use rand::seq::SliceRandom;
+use strum::IntoEnumIterator;
 use strum_macros::{EnumIter, EnumCount};
 
-#[derive(Default, EnumIter, Debug, Clone, EnumCount)]
+#[derive(Default, EnumIter, Debug, Clone, PartialEq, EnumCount)]
 pub enum PickupKind {
     #[default]
     MaxHealthUp,
@@ -14,25 +14,62 @@ pub enum PickupKind {
     ShotSpeedUp,
     FireRateUp,
     PiercingUp,
+    RegenUp,
+    CritChanceUp,
+    CritDamageUp,
+    ExplosiveShots,
+    ChainLightning,
+    Laser,
+    // dropped by enemies, never found in shops or treasure rooms
+    Heart,
 }
 
+impl PickupKind {
+    pub fn random() -> PickupKind {
+        let eligible: Vec<PickupKind> = PickupKind::iter().filter(|k| k.is_loot()).collect();
+        return eligible.choose(&mut rand::thread_rng()).cloned().unwrap_or_default();
+    }
+
+    /// Whether shops, treasure rooms and random drops can hand this out.
+    pub fn is_loot(&self) -> bool {
+        return !matches!(self, PickupKind::Heart);
+    }
+}
+
+// score it costs to take a pickup from a shop, per floor
+pub const SHOP_PRICE: i32 = 500;
+// fire rate pickups stop speeding the gun up once it fires this fraction as far apart
+pub const MIN_FIRE_INTERVAL_MULTIPLY: f32 = 0.1;
+
 #[derive(Component, Default)]
 pub struct Pickup {
     pub kind: PickupKind,
+    // score the player pays to take it, 0 for free drops
+    pub price: i32,
 }
 
 impl Pickup {
-    pub fn apply(&self, player: &mut Player) {
+    pub fn apply(&self, player: &mut Player, tuning: &PickupTuning) {
         dbg!(&self.kind);
         match self.kind {
-            PickupKind::DamageUp => player.stats.damage.multiply += 0.10,
+            PickupKind::DamageUp => player.stats.damage.multiply += tuning.damage_up,
             PickupKind::MaxHealthUp => {
-                player.stats.max_health.add += 20.0;
-                player.health += 20.0;
+                player.stats.max_health.add += tuning.max_health_up;
+                player.heal(tuning.max_health_up);
             }
-            PickupKind::ShotSpeedUp => player.stats.shot_speed.multiply += 0.1,
-            PickupKind::FireRateUp => player.stats.fire_interval.multiply -= 0.1,
-            PickupKind::PiercingUp => player.stats.piercing.add += 1.0,
+            PickupKind::ShotSpeedUp => player.stats.shot_speed.multiply += tuning.shot_speed_up,
+            PickupKind::FireRateUp => {
+                let multiply = player.stats.fire_interval.multiply - tuning.fire_rate_up;
+                player.stats.fire_interval.multiply = multiply.max(MIN_FIRE_INTERVAL_MULTIPLY);
+            }
+            PickupKind::PiercingUp => player.stats.piercing.add += tuning.piercing_up,
+            PickupKind::RegenUp => player.stats.regen.add += tuning.regen_up,
+            PickupKind::CritChanceUp => player.stats.crit_chance.add += tuning.crit_chance_up,
+            PickupKind::CritDamageUp => player.stats.crit_multiplier.add += tuning.crit_damage_up,
+            PickupKind::ExplosiveShots => player.stats.explosion_radius.add += tuning.explosion_radius,
+            PickupKind::ChainLightning => player.stats.chain_jumps.add += tuning.chain_jumps,
+            PickupKind::Laser => player.equip(Weapon::Beam),
+            PickupKind::Heart => player.heal(tuning.heart_heal),
         }
     }
 
@@ -43,6 +80,13 @@ impl Pickup {
             PickupKind::FireRateUp => Color::GREEN,
             PickupKind::ShotSpeedUp => Color::LIME_GREEN,
             PickupKind::PiercingUp => Color::PURPLE,
+            PickupKind::RegenUp => Color::PINK,
+            PickupKind::CritChanceUp => Color::YELLOW,
+            PickupKind::CritDamageUp => Color::GOLD,
+            PickupKind::ExplosiveShots => Color::ORANGE_RED,
+            PickupKind::ChainLightning => Color::CYAN,
+            PickupKind::Laser => Color::rgb(0.3, 1.0, 1.5),
+            PickupKind::Heart => Color::rgb(2.0, 0.3, 0.4),
         }
     }
 }
@@ -58,19 +102,11 @@ impl PickupBundle {
     pub const PICKUP_DIMS: Vec2 = vec2(50.0, 50.0);
 
     pub fn new(tex: Handle<ColorMaterial>, mesh: Mesh2dHandle) -> PickupBundle {
-        let r = rand::thread_rng().gen_range(0..PickupKind::COUNT);
-        let mut i = 0;
-        let mut kind = PickupKind::default();
-        for k in PickupKind::iter() {
-            if i == r {
-                kind = k;
-                break;
-            }
-            i += 1;
-        }
-
         return PickupBundle {
-            pickup: Pickup { kind: kind },
+            pickup: Pickup {
+                kind: PickupKind::random(),
+                price: 0,
+            },
             sprite: PhysicsSpriteBundle::new(&PickupBundle::PICKUP_DIMS, &Vec2::ZERO, tex, mesh),
         };
     }
@@ -78,13 +114,17 @@ impl PickupBundle {
     pub fn from_kind(
         pos: Vec2,
         kind: PickupKind,
-        mut colors: ResMut<Assets<ColorMaterial>>,
-        mut meshes: ResMut<Assets<Mesh>>,
+        price: i32,
+        colors: &mut Assets<ColorMaterial>,
+        meshes: &mut Assets<Mesh>,
     ) -> PickupBundle {
         let dims = vec2(40.0, 40.0);
         let mesh: Mesh = shape::Box::new(dims.x, dims.y, 1.0).into();
         PickupBundle {
-            pickup: Pickup { kind: kind.clone() },
+            pickup: Pickup {
+                kind: kind.clone(),
+                price,
+            },
             sprite: PhysicsSpriteBundle::new(
                 &dims,
                 &pos,
@@ -98,10 +138,12 @@ impl PickupBundle {
 pub fn tick(
     mut commands: Commands,
     time: Res<Time>,
-    mut game: ResMut<Game>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    tuning: Res<Tuning>,
     mut pickups: Query<(Entity, &mut Pickup, &mut Transform, &Collider, &mut Handle<ColorMaterial>)>,
     mut player: Query<(&mut Player, &mut Transform, &Collider, Without<Pickup>)>,
+    mut particles: EventWriter<EmitParticles>,
+    mut sounds: EventWriter<PlaySound>,
     rapier_ctx: Res<RapierContext>,
 ) {
     for (pickup_entity, pickup, transform, collider, material) in pickups.iter_mut() {
@@ -116,12 +158,36 @@ pub fn tick(
             QueryFilter::default(),
             |entity| {
                 if let Ok(mut player) = player.get_mut(entity) {
-                    pickup.apply(&mut player.0);
-                    pickup.apply(&mut game.player);
-                    commands.entity(pickup_entity).despawn();
+                    if !player.0.downed && player.0.score >= pickup.price {
+                        player.0.score -= pickup.price;
+                        pickup.apply(&mut player.0, &tuning.pickups);
+                        sounds.send(PlaySound::at(Sfx::Pickup, transform.translation.truncate()));
+                        particles.send(EmitParticles {
+                            effect: "pickup",
+                            position: transform.translation.truncate(),
+                            direction: Vec2::ZERO,
+                        });
+                        commands.entity(pickup_entity).despawn();
+                        return false;
+                    }
                 }
                 true
             },
         );
     }
 }
+
+#[test]
+fn fire_rate_pickups_stop_at_the_floor() {
+    let mut player = Player::default();
+    let tuning = PickupTuning::default();
+    let pickup = Pickup {
+        kind: PickupKind::FireRateUp,
+        price: 0,
+    };
+    for _ in 0..20 {
+        pickup.apply(&mut player, &tuning);
+    }
+    assert_eq!(player.stats.fire_interval.multiply, MIN_FIRE_INTERVAL_MULTIPLY);
+    assert!(player.stats.fire_interval.value() > 0.0);
+}
@@ -2,12 +2,35 @@ use std::{f32::consts::PI, time::Duration};
 
 use crate::*;
 use bevy::{sprite::Mesh2dHandle, time::Stopwatch};
+use input::{Action, ActionState, AimMode, InputDevice};
 use bevy_rapier2d::parry::utils::Interval;
-use map::clamp_position;
+use map::{move_and_slide, Arena};
 use physics_sprite::PhysicsSpriteBundle;
+use rand::Rng;
+use serde::Deserialize;
 use std::fmt;
 
-const PLAYER_DIMS: Vec2 = vec2(40.0, 45.0);
+const DASH_IMPULSE: f32 = 25.0;
+const DASH_COOLDOWN: f32 = 1.0;
+
+pub const MAX_PLAYERS: usize = 4;
+const PLAYER_SPACING: f32 = 60.0;
+const PLAYER_COLORS: [Color; MAX_PLAYERS] = [
+    Color::hsla(130.0, 1.0, 0.5, 1.0),
+    Color::hsla(200.0, 1.0, 0.5, 1.0),
+    Color::hsla(30.0, 1.0, 0.5, 1.0),
+    Color::hsla(300.0, 1.0, 0.5, 1.0),
+];
+const REVIVE_RADIUS: f32 = 80.0;
+// seconds a partner has to stand next to a downed player
+const REVIVE_TIME: f32 = 3.0;
+// fraction of max health a revived player comes back with
+const REVIVE_HEALTH: f32 = 0.3;
+// overheal turns into shield, up to this fraction of max health, which then wears off
+const MAX_SHIELD: f32 = 0.5;
+const SHIELD_DECAY: f32 = 4.0;
+// shots remembered for counting crits, far more than can be in flight at once
+const MAX_CRIT_SHOTS: usize = 64;
 
 pub struct Stat {
     pub base: f32,
@@ -30,32 +53,89 @@ impl Stat {
     }
 }
 
-#[derive(Component)]
-pub struct Stats {
-    pub damage: Stat,
-    pub speed: Stat,
-    pub max_health: Stat,
-    pub shot_duration: Stat,
-    pub shot_speed: Stat,
-    pub shot_size: Stat,
-    pub fire_interval: Stat,
-    pub piercing: Stat,
-    pub mass: Stat,
+// every stat is listed once here, with its starting value, and gets a field on both
+// `BaseStats` and `Stats` plus an entry in the console's lookup
+macro_rules! stats {
+    ($($name:ident: $default:expr),* $(,)?) => {
+        /// Starting values for every player stat, before any pickups.
+        #[derive(Deserialize, Clone, Debug)]
+        #[serde(default)]
+        pub struct BaseStats {
+            $(pub $name: f32,)*
+        }
+
+        impl Default for BaseStats {
+            fn default() -> Self {
+                BaseStats {
+                    $($name: $default,)*
+                }
+            }
+        }
+
+        #[derive(Component)]
+        pub struct Stats {
+            $(pub $name: Stat,)*
+        }
+
+        impl Stats {
+            pub const NAMES: &'static [&'static str] = &[$(stringify!($name)),*];
+
+            /// Looks a stat up by its field name, for the dev console.
+            pub fn get_mut(&mut self, name: &str) -> Option<&mut Stat> {
+                match name {
+                    $(stringify!($name) => Some(&mut self.$name),)*
+                    _ => None,
+                }
+            }
+
+            fn new(base: &BaseStats) -> Stats {
+                Stats {
+                    $($name: Stat::new(base.$name),)*
+                }
+            }
+
+            /// Swaps in new base values, leaving pickup bonuses alone.
+            pub fn rebase(&mut self, base: &BaseStats) {
+                $(self.$name.base = base.$name;)*
+            }
+        }
+    };
+}
+
+stats! {
+    damage: 60.0,
+    speed: 100.0,
+    max_health: 100.0,
+    shot_duration: 1.0,
+    shot_speed: 500.0,
+    shot_size: 10.0,
+    fire_interval: 0.25,
+    piercing: 1.0,
+    mass: 100.0,
+    // health regained per second
+    regen: 0.0,
+    crit_chance: 0.05,
+    crit_multiplier: 2.0,
+    // each shot's damage is off by up to this fraction either way
+    damage_variance: 0.1,
+    knockback: 1.0,
+    // shots blow up in this radius when they hit something
+    explosion_radius: 0.0,
+    // enemies the lightning jumps on to after a hit
+    chain_jumps: 0.0,
 }
 
 impl Stats {
-    fn new() -> Stats {
-        Stats {
-            damage: Stat::new(60.0),
-            speed: Stat::new(100.0),
-            max_health: Stat::new(100.0),
-            fire_interval: Stat::new(0.25),
-            shot_speed: Stat::new(500.0),
-            shot_duration: Stat::new(1.0),
-            shot_size: Stat::new(10.0),
-            piercing: Stat::new(1.0),
-            mass: Stat::new(100.0),
+    /// Damage for a single shot, and whether it crit.
+    pub fn roll_damage(&self) -> (f32, bool) {
+        let mut rng = rand::thread_rng();
+        let variance = self.damage_variance.value().clamp(0.0, 1.0);
+        let mut damage = self.damage.value() * (1.0 + rng.gen_range(-variance..=variance));
+        let crit = rng.gen::<f32>() < self.crit_chance.value();
+        if crit {
+            damage *= self.crit_multiplier.value();
         }
+        return (damage, crit);
     }
 }
 
@@ -74,27 +154,51 @@ impl fmt::Display for Stat {
 
 #[derive(Component)]
 pub struct Player {
+    pub slot: usize,
+    pub color: Color,
     pub position: Vec2,
     pub direction: Vec2,
+    pub aim_pos: Vec2,
     pub momentum: Vec2,
-    pub id: Option<Entity>,
     pub shot_clock: Stopwatch,
+    pub dash_clock: Stopwatch,
     pub score: i32,
+    // critical hits landed this run
+    pub crits: i32,
+    // numbers each shot fired, gun or beam
+    pub shots: u32,
+    // recent shots that already counted a crit
+    crit_shots: Vec<u32>,
     pub health: f32,
+    pub shield: f32,
+
+    pub downed: bool,
+    pub revive_progress: f32,
 
+    pub weapon: Weapon,
     pub stats: Stats,
 }
 
 impl Default for Player {
     fn default() -> Self {
         return Player {
+            slot: 0,
+            color: Color::hsla(130.0, 1.0, 0.5, 1.0),
             position: Vec2::ZERO,
             shot_clock: Stopwatch::new(),
+            dash_clock: Stopwatch::new(),
             direction: Vec2::new(1.0, 0.0),
+            aim_pos: Vec2::new(1.0, 0.0),
             health: 100.0,
+            shield: 0.0,
             score: 0,
-            id: None,
-            stats: Stats::new(),
+            crits: 0,
+            shots: 0,
+            crit_shots: vec![],
+            downed: false,
+            revive_progress: 0.0,
+            weapon: Weapon::Gun,
+            stats: Stats::new(&BaseStats::default()),
             momentum: Vec2::ZERO,
         };
     }
@@ -103,6 +207,9 @@ impl Default for Player {
 #[derive(Default, Bundle)]
 pub struct PlayerBundle {
     player: Player,
+    device: InputDevice,
+    actions: ActionState,
+    flash: HitFlash,
 
     #[bundle]
     sprite: PhysicsSpriteBundle,
@@ -110,7 +217,7 @@ pub struct PlayerBundle {
 
 #[derive(Default, Component)]
 pub struct Cursor {
-    screen_pos: Vec2,
+    player: Option<Entity>,
     world_pos: Vec2,
 }
 
@@ -123,43 +230,160 @@ pub struct CursorBundle {
 }
 
 impl PlayerBundle {
-    pub fn new(material: Handle<ColorMaterial>, mesh: Mesh2dHandle) -> PlayerBundle {
+    pub fn new(
+        player: Player,
+        device: InputDevice,
+        dims: Vec2,
+        material: Handle<ColorMaterial>,
+        mesh: Mesh2dHandle,
+    ) -> PlayerBundle {
+        let position = player.position;
         return PlayerBundle {
-            player: Player::default(),
-            sprite: PhysicsSpriteBundle::new(&PLAYER_DIMS, &Vec2::ZERO, material, mesh),
+            player,
+            device,
+            actions: ActionState::default(),
+            flash: HitFlash::default(),
+            sprite: PhysicsSpriteBundle::new(&dims, &position, material, mesh),
         };
     }
 }
 
 impl Player {
+    /// Heals up to max health; whatever is left over becomes shield.
+    pub fn heal(&mut self, amount: f32) {
+        let max_health = self.stats.max_health.value();
+        let overheal = (self.health + amount - max_health).max(0.0);
+        self.health = (self.health + amount).min(max_health);
+        self.shield = (self.shield + overheal).min(max_health * MAX_SHIELD);
+    }
+
+    /// Numbers a new shot, so [`Player::count_crit`] can tell them apart.
+    pub fn next_shot(&mut self) -> u32 {
+        self.shots += 1;
+        return self.shots;
+    }
+
+    /// Counts a crit once per shot, however many enemies it pierces or the beam touches.
+    pub fn count_crit(&mut self, shot: u32) {
+        if self.crit_shots.contains(&shot) {
+            return;
+        }
+        self.crit_shots.push(shot);
+        if self.crit_shots.len() > MAX_CRIT_SHOTS {
+            self.crit_shots.remove(0);
+        }
+        self.crits += 1;
+    }
+
+    /// Switches weapon, swapping the old weapon's crit bonus for the new one's.
+    pub fn equip(&mut self, weapon: Weapon) {
+        let (old_chance, old_multiplier) = self.weapon.crit_bonus();
+        let (chance, multiplier) = weapon.crit_bonus();
+        self.stats.crit_chance.add += chance - old_chance;
+        self.stats.crit_multiplier.add += multiplier - old_multiplier;
+        self.weapon = weapon;
+    }
+
+    /// Shield soaks up damage before health does. Returns the health lost.
+    pub fn take_damage(&mut self, amount: f32) -> f32 {
+        let absorbed = amount.min(self.shield);
+        self.shield -= absorbed;
+        // overkill past zero isn't health lost
+        let lost = (amount - absorbed).min(self.health.max(0.0));
+        self.health -= amount - absorbed;
+        return lost;
+    }
+
     fn tick_cooldowns(self: &mut Self, delta: Duration) {
         self.shot_clock.tick(delta);
+        self.dash_clock.tick(delta);
+    }
+}
+
+pub fn spawn_players(
+    commands: &mut Commands,
+    count: usize,
+    start: Vec2,
+    mesh: Mesh2dHandle,
+    materials: &mut Assets<ColorMaterial>,
+    tuning: &Tuning,
+) {
+    for slot in 0..count {
+        // the first player keeps the keyboard, everyone else gets a pad from assign_gamepads
+        let device = if count == 1 {
+            InputDevice::Any
+        } else if slot == 0 {
+            InputDevice::KeyboardMouse
+        } else {
+            InputDevice::WaitingForPad
+        };
+        let color = PLAYER_COLORS[slot % PLAYER_COLORS.len()];
+        let player = Player {
+            slot,
+            color,
+            position: start + vec2((slot as f32 - (count - 1) as f32 / 2.0) * PLAYER_SPACING, 0.0),
+            health: tuning.player.stats.max_health,
+            stats: Stats::new(&tuning.player.stats),
+            ..default()
+        };
+
+        let id = commands
+            .spawn(PlayerBundle::new(
+                player,
+                device,
+                tuning.player.dims,
+                materials.add(ColorMaterial { color, texture: None }),
+                mesh.clone(),
+            ))
+            .id();
+
+        commands.spawn(CursorBundle {
+            cursor: Cursor {
+                player: Some(id),
+                ..default()
+            },
+            sprite: SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(vec2(10.0, 10.0)),
+                    ..default()
+                },
+                ..default()
+            },
+        });
     }
 }
 
 pub fn tick_cursor(
     mut game: ResMut<Game>,
+    settings: Res<Settings>,
     windows: Res<Windows>,
-    camera: Query<&OrthographicProjection, (With<Camera>, Without<Cursor>)>,
-    mut cursor: Query<(&mut Transform, &mut Cursor)>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut players: Query<(&mut Player, &ActionState)>,
+    mut cursors: Query<(&mut Transform, &mut Cursor)>,
 ) {
-    if let Ok(projection) = camera.get_single() {
-        if let Some(window) = windows.get_primary() {
-            game.window_size = vec2(window.width(), window.height());
-            if let Some(mouse_pos) = window.cursor_position() {
-                if let Ok((mut transform, mut cursor)) = cursor.get_single_mut() {
-                    cursor.screen_pos = mouse_pos;
-                    let world_pos = vec2(
-                        lerp(projection.left, projection.right, game.mouse_rel_pos.x),
-                        lerp(projection.bottom, projection.top, game.mouse_rel_pos.y),
-                    );
-                    game.mouse_rel_pos = cursor.screen_pos / game.window_size;
-                    cursor.world_pos = world_pos;
-                    transform.translation = world_pos.extend(33.0);
-                }
+    if let Some(window) = windows.get_primary() {
+        game.window_size = vec2(window.width(), window.height());
+        if let Some(mouse_pos) = window.cursor_position() {
+            if let Some(world_pos) = cursor_to_world(window, cameras.iter(), mouse_pos) {
+                game.mouse_rel_pos = mouse_pos / game.window_size;
+                game.mouse_world_pos = world_pos;
             }
         }
     }
+
+    for (mut transform, mut cursor) in cursors.iter_mut() {
+        if let Some(Ok((mut player, actions))) = cursor.player.map(|p| players.get_mut(p)) {
+            player.aim_pos = if actions.aim_mode == AimMode::Stick {
+                let aim_dir = if actions.aim_dir == Vec2::ZERO { player.direction } else { actions.aim_dir };
+                player.position + aim_dir * settings.sticks.aim_radius
+            } else {
+                game.mouse_world_pos
+            };
+            cursor.world_pos = player.aim_pos;
+            transform.translation = cursor.world_pos.extend(33.0);
+        }
+    }
 }
 
 pub fn make_mesh() -> Mesh {
@@ -168,90 +392,157 @@ pub fn make_mesh() -> Mesh {
 
 pub fn tick(
     mut commands: Commands,
-    input: Res<Input<KeyCode>>,
-    mouse: Res<Input<MouseButton>>,
-    mut game: ResMut<Game>,
+    game: Res<Game>,
     time: Res<Time>,
-    mut player: Query<(&mut Mesh2dHandle, &mut Transform), With<Player>>,
-    cursor: Query<&Cursor>,
+    arena: Res<Arena>,
+    tuning: Res<Tuning>,
+    rapier_ctx: Res<RapierContext>,
+    mut bullet_pool: ResMut<Pool<Bullet>>,
+    mut particles: EventWriter<EmitParticles>,
+    mut sounds: EventWriter<PlaySound>,
+    mut players: Query<(Entity, &mut Player, &ActionState, &mut Transform, &Collider)>,
 ) {
-    if input.pressed(KeyCode::Escape) {
+    if players.iter().any(|(_, _, actions, _, _)| actions.pressed(Action::Pause)) {
         commands.insert_resource(NextState(GameState::Paused));
         return;
     }
 
-    if game.player.health <= 0.0  {
-        commands.insert_resource(NextState(GameState::GameOver));
-        return;
-    }
-
-    game.player.tick_cooldowns(time.delta());
+    let dt = time.delta_seconds();
+    for (_, mut player, _, _, _) in players.iter_mut() {
+        let max_health = player.stats.max_health.value();
+        player.health = player.health.min(max_health);
+        player.shield = (player.shield - SHIELD_DECAY * dt).max(0.0);
+        if !player.downed && player.health > 0.0 {
+            player.health = (player.health + player.stats.regen.value() * dt).min(max_health);
+        }
 
-    if let Ok(cursor) = cursor.get_single() {
-        game.player.direction = (cursor.world_pos - game.player.position).normalize();
-        game.mouse_world_pos = cursor.world_pos;
+        if player.health <= 0.0 && !player.downed {
+            player.health = 0.0;
+            player.shield = 0.0;
+            player.downed = true;
+            player.revive_progress = 0.0;
+            player.momentum = Vec2::ZERO;
+        }
     }
 
-    let mut input_dir = Vec2::ZERO;
-    if input.pressed(KeyCode::Right) || input.pressed(KeyCode::D) {
-        input_dir.x = 1.0;
-    } else if input.pressed(KeyCode::Left) || input.pressed(KeyCode::A) {
-        input_dir.x = -1.0;
+    // the run only ends once nobody is left standing
+    if players.iter().all(|(_, player, _, _, _)| player.downed) {
+        commands.insert_resource(NextState(GameState::GameOver));
+        return;
     }
 
-    if input.pressed(KeyCode::Up) || input.pressed(KeyCode::W) {
-        input_dir.y = 1.0;
-    } else if input.pressed(KeyCode::Down) || input.pressed(KeyCode::S) {
-        input_dir.y = -1.0;
-    }
+    let standing: Vec<Vec2> = players
+        .iter()
+        .filter(|(_, player, _, _, _)| !player.downed)
+        .map(|(_, player, _, _, _)| player.position)
+        .collect();
+
+    for (entity, mut player, actions, mut transform, collider) in players.iter_mut() {
+        player.tick_cooldowns(time.delta());
+
+        if player.downed {
+            // a partner standing close by slowly brings a downed player back
+            let partner_close = standing.iter().any(|&pos| pos.distance(player.position) < REVIVE_RADIUS);
+            if partner_close {
+                player.revive_progress += time.delta_seconds() / REVIVE_TIME;
+            } else {
+                player.revive_progress = 0.0;
+            }
+            if player.revive_progress >= 1.0 {
+                player.downed = false;
+                player.revive_progress = 0.0;
+                player.health = player.stats.max_health.value() * REVIVE_HEALTH;
+            }
+            continue;
+        }
 
-    input_dir = input_dir.normalize_or_zero();
+        player.direction = (player.aim_pos - player.position).normalize_or_zero();
+        if player.direction == Vec2::ZERO {
+            player.direction = Vec2::X;
+        }
+
+        let mut input_dir = Vec2::ZERO;
+        if actions.pressed(Action::MoveRight) {
+            input_dir.x = 1.0;
+        } else if actions.pressed(Action::MoveLeft) {
+            input_dir.x = -1.0;
+        }
 
-    let time = time.delta_seconds();
-    let speed = game.player.stats.speed.value();
-    let force = speed * input_dir * time;
-    let momentum = game.player.momentum + force;
-    game.player.position += momentum;
+        if actions.pressed(Action::MoveUp) {
+            input_dir.y = 1.0;
+        } else if actions.pressed(Action::MoveDown) {
+            input_dir.y = -1.0;
+        }
 
-    let drag = 0.75;
-    let drag_force = drag * game.player.momentum.normalize_or_zero() * game.player.momentum.length_squared() * time;
-    game.player.momentum = momentum - drag_force;
+        input_dir = input_dir.normalize_or_zero();
+        if input_dir == Vec2::ZERO {
+            // analog, so a half-pushed stick walks at half speed
+            input_dir = actions.move_stick;
+        }
 
-    game.player.position = clamp_position(&game.player.position);
+        if actions.just_pressed(Action::Dash) && player.dash_clock.elapsed_secs() >= DASH_COOLDOWN {
+            let dash_dir = if input_dir == Vec2::ZERO { player.direction } else { input_dir };
+            player.momentum += DASH_IMPULSE * dash_dir;
+            player.dash_clock.reset();
+        }
 
-    let fire_interval = game.player.stats.fire_interval.value()
-        * if input.pressed(KeyCode::LShift) {
-            0.1
-        } else {
-            1.0
-        };
-    if (input.pressed(KeyCode::Space) || mouse.pressed(MouseButton::Left))
-        && game.player.shot_clock.elapsed_secs() >= fire_interval
-    {
-        commands.spawn(BulletBundle::new(
-            Bullet {
-                shooter: Some(game.player.id.unwrap()),
-                position: game.player.position,
+        let speed = player.stats.speed.value() * arena.tile_at(player.position).speed_scale();
+        let force = speed * input_dir * dt;
+        let momentum = player.momentum + force;
+        let target = arena.clamp_position(&(player.position + momentum));
+        player.position = move_and_slide(&rapier_ctx, collider, player.position, target);
+
+        let drag_force = tuning.player.drag * player.momentum.normalize_or_zero() * player.momentum.length_squared() * dt;
+        player.momentum = momentum - drag_force;
+
+        let fire_interval = player.stats.fire_interval.value()
+            * if actions.pressed(Action::RapidFire) {
+                0.1
+            } else {
+                1.0
+            };
+        // the beam is drawn and dealt by beam::tick instead
+        if player.weapon == Weapon::Gun
+            && actions.pressed(Action::Fire)
+            && player.shot_clock.elapsed_secs() >= fire_interval
+        {
+            let (damage, crit) = player.stats.roll_damage();
+            let shot = player.next_shot();
+            let radius = player.stats.shot_size.value();
+            let mesh = game.handles.bullet_mesh.clone();
+            let bullet = Bullet {
+                shooter: Some(entity),
+                position: player.position,
                 hits_player: false,
-                velocity: game.player.stats.shot_speed.value() * game.player.direction,
-                damage: game.player.stats.damage.value(),
-                radius: game.player.stats.shot_size.value(),
+                velocity: player.stats.shot_speed.value() * player.direction,
+                damage,
+                crit,
+                shot,
+                radius,
+                knockback: player.stats.knockback.value(),
+                explosion_radius: player.stats.explosion_radius.value(),
+                chain_jumps: player.stats.chain_jumps.value() as i32,
                 hit_enemies: Default::default(),
-                piercing: game.player.stats.piercing.value() as i32,
-            },
-            game.handles.bullet_mesh.clone(),
-        ));
-
-        game.player.shot_clock.reset();
-    }
+                piercing: player.stats.piercing.value() as i32,
+            };
+            // at the bullet cap nothing comes out, so there's nothing to flash or wait on either
+            if bullet_pool.spawn_with(&mut commands, || BulletBundle::new(radius, mesh), bullet.fired()).is_some() {
+                particles.send(EmitParticles {
+                    effect: "muzzle_flash",
+                    position: player.position + player.direction * tuning.player.dims.y / 2.0,
+                    direction: player.direction,
+                });
+                sounds.send(PlaySound::at(Sfx::Shoot, player.position));
+                player.shot_clock.reset();
+            }
+        }
 
-    let angle = game.player.direction.y.atan2(game.player.direction.x);
-    if let Ok((mut _sprite, mut transform)) = player.get_single_mut() {
+        let angle = player.direction.y.atan2(player.direction.x);
         *transform = Transform {
             translation: Vec3::new(
-                game.player.position.x,
-                game.player.position.y,
-                z_from_y(game.player.position.y),
+                player.position.x,
+                player.position.y,
+                z_from_y(player.position.y),
             ),
             rotation: Quat::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), angle - PI / 2.0),
             ..default()
@@ -0,0 +1,247 @@
+use std::marker::PhantomData;
+
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+// parked entities wait out here, well away from anything they could touch
+const PARKING_SPOT: Vec3 = Vec3::new(-100000.0, -100000.0, -1000.0);
+// and each gets its own spot in a grid, spaced wider than any collider, so parked bullets
+// don't pile up into one huge clump of broad-phase pairs
+const PARKING_SPACING: f32 = 1000.0;
+const PARKING_ROW: usize = 64;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    // most bullets in flight at once; shots past this are simply not fired
+    pub bullets: usize,
+    // most lightning segments and explosion flashes on screen at once, each
+    pub effects: usize,
+    pub particles: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            bullets: 512,
+            effects: 256,
+            particles: 2048,
+        }
+    }
+}
+
+/// A pooled entity that's been handed back: hidden, out of the way, and skipped by gameplay
+/// until the pool gives it out again.
+#[derive(Component)]
+pub struct Parked;
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct PoolStats {
+    pub live: usize,
+    pub free: usize,
+    // entities created from scratch, versus handed out again
+    pub spawned: usize,
+    pub reused: usize,
+    // spawns turned down for being over the cap
+    pub refused: usize,
+}
+
+/// Recycles entities marked with `T` instead of despawning them, so short-lived things like
+/// bullets don't churn archetypes and colliders every frame.
+#[derive(Resource)]
+pub struct Pool<T> {
+    pub cap: usize,
+    free: Vec<Entity>,
+    stats: PoolStats,
+    marker: PhantomData<T>,
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Pool {
+            cap: usize::MAX,
+            free: vec![],
+            stats: PoolStats::default(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Component> Pool<T> {
+    /// Hands out a parked entity with `bundle` put back on it, or spawns a new one. Gives
+    /// nothing if the pool is already at its cap.
+    pub fn spawn(&mut self, commands: &mut Commands, bundle: impl Bundle) -> Option<Entity> {
+        return self.spawn_with(commands, || (), bundle);
+    }
+
+    /// Like [`Pool::spawn`], but only a new entity gets `fixed`, the parts that are the same
+    /// every time. A parked one keeps what it already has and just gets `state` written over
+    /// it, so things like colliders aren't torn down and rebuilt on every reuse.
+    pub fn spawn_with<B: Bundle>(
+        &mut self,
+        commands: &mut Commands,
+        fixed: impl FnOnce() -> B,
+        state: impl Bundle,
+    ) -> Option<Entity> {
+        if self.stats.live >= self.cap {
+            self.stats.refused += 1;
+            return None;
+        }
+        self.stats.live += 1;
+        match self.free.pop() {
+            Some(entity) => {
+                self.stats.reused += 1;
+                commands.entity(entity).remove::<Parked>().insert(state);
+                return Some(entity);
+            }
+            None => {
+                self.stats.spawned += 1;
+                return Some(commands.spawn(fixed()).insert(state).id());
+            }
+        }
+    }
+
+    /// Parks `entity` for reuse instead of despawning it.
+    pub fn release(&mut self, commands: &mut Commands, entity: Entity) {
+        if self.free.contains(&entity) {
+            return;
+        }
+        // the free list is a stack, so its length is a spot nobody else parked is using
+        let spot = self.free.len();
+        let offset = Vec3::new((spot % PARKING_ROW) as f32, (spot / PARKING_ROW) as f32, 0.0) * PARKING_SPACING;
+        commands.entity(entity).insert((
+            Parked,
+            Visibility { is_visible: false },
+            Transform::from_translation(PARKING_SPOT + offset),
+        ));
+        self.free.push(entity);
+        self.stats.live = self.stats.live.saturating_sub(1);
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        return PoolStats {
+            free: self.free.len(),
+            ..self.stats
+        };
+    }
+}
+
+/// Recounts what's live, since pooled entities can still be despawned outright when a floor
+/// is torn down.
+pub fn tick<T: Component>(mut pool: ResMut<Pool<T>>, live: Query<(), (With<T>, Without<Parked>)>) {
+    pool.stats.live = live.iter().count();
+}
+
+pub fn apply_caps(
+    settings: Res<Settings>,
+    mut bullets: ResMut<Pool<Bullet>>,
+    mut arcs: ResMut<Pool<LightningArc>>,
+    mut flashes: ResMut<Pool<ExplosionFlash>>,
+    mut particles: ResMut<Pool<Particle>>,
+) {
+    if settings.is_changed() {
+        bullets.cap = settings.pools.bullets;
+        arcs.cap = settings.pools.effects;
+        flashes.cap = settings.pools.effects;
+        particles.cap = settings.pools.particles;
+    }
+}
+
+#[cfg(test)]
+#[derive(Component)]
+struct Thing;
+
+#[test]
+fn pool_reuses_parked_entities_and_respects_cap() {
+    use bevy::ecs::system::CommandQueue;
+
+    let mut world = World::new();
+    let mut queue = CommandQueue::default();
+    let mut pool = Pool::<Thing> { cap: 2, ..default() };
+
+    let (a, b, c) = {
+        let mut commands = Commands::new(&mut queue, &world);
+        let a = pool.spawn(&mut commands, Thing);
+        let b = pool.spawn(&mut commands, Thing);
+        let c = pool.spawn(&mut commands, Thing);
+        (a, b, c)
+    };
+    queue.apply(&mut world);
+    assert!(a.is_some() && b.is_some());
+    assert!(c.is_none());
+    assert_eq!(pool.stats().refused, 1);
+
+    let a = a.unwrap();
+    {
+        let mut commands = Commands::new(&mut queue, &world);
+        pool.release(&mut commands, a);
+        pool.release(&mut commands, a);
+    }
+    queue.apply(&mut world);
+    assert!(world.get::<Parked>(a).is_some());
+    assert_eq!(pool.stats().free, 1);
+
+    let again = {
+        let mut commands = Commands::new(&mut queue, &world);
+        pool.spawn(&mut commands, Thing)
+    };
+    queue.apply(&mut world);
+    assert_eq!(again, Some(a));
+    assert!(world.get::<Parked>(a).is_none());
+    assert_eq!(pool.stats().spawned, 2);
+    assert_eq!(pool.stats().reused, 1);
+}
+
+#[test]
+fn parked_bullets_are_out_of_reach() {
+    use bevy::ecs::system::CommandQueue;
+
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0));
+    let mut queue = CommandQueue::default();
+    let mut pool = Pool::<Bullet>::default();
+
+    let fire = |pool: &mut Pool<Bullet>, queue: &mut CommandQueue, app: &mut App| {
+        let bullet = {
+            let mut commands = Commands::new(queue, &app.world);
+            pool.spawn_with(&mut commands, || BulletBundle::new(10.0, default()), Bullet::default().fired())
+        };
+        queue.apply(&mut app.world);
+        app.update();
+        return bullet.unwrap();
+    };
+    let park = |pool: &mut Pool<Bullet>, queue: &mut CommandQueue, app: &mut App, entity| {
+        let mut commands = Commands::new(queue, &app.world);
+        pool.release(&mut commands, entity);
+        queue.apply(&mut app.world);
+        app.update();
+    };
+    let cast = |app: &App| {
+        let rapier_ctx = app.world.resource::<RapierContext>();
+        let ball = Collider::ball(10.0);
+        return rapier_ctx.cast_shape(vec2(-50.0, 0.0), 0.0, vec2(100.0, 0.0), &ball, 1.0, QueryFilter::default());
+    };
+
+    let a = fire(&mut pool, &mut queue, &mut app);
+    let b = fire(&mut pool, &mut queue, &mut app);
+    assert!(cast(&app).is_some());
+    park(&mut pool, &mut queue, &mut app, a);
+    park(&mut pool, &mut queue, &mut app, b);
+    assert!(cast(&app).is_none());
+
+    // and parked apart from each other, not stacked on one spot
+    let a_pos = app.world.get::<Transform>(a).unwrap().translation;
+    let b_pos = app.world.get::<Transform>(b).unwrap().translation;
+    assert!(a_pos.distance(b_pos) >= PARKING_SPACING);
+
+    // reusing one brings it back without touching its collider
+    let parked_at = app.world.read_change_tick();
+    let again = fire(&mut pool, &mut queue, &mut app);
+    assert_eq!(again, b);
+    assert!(cast(&app).is_some());
+    let ticks = app.world.entity(again).get_change_ticks::<Collider>().unwrap();
+    assert!(!ticks.is_changed(parked_at, app.world.read_change_tick()));
+}
@@ -32,10 +32,16 @@ pub fn rand_pos_vec2() -> Vec2 {
     );
 }
 
+/// A random unit vector. Picks an angle rather than normalizing a point in a square,
+/// which would favour the diagonals.
 pub fn rand_norm_vec2() -> Vec2 {
-    return Vec2::new(
-        rand::thread_rng().gen_range(-1.0..1.0),
-        rand::thread_rng().gen_range(-1.0..1.0),
-    )
-    .normalize();
+    let angle = rand::thread_rng().gen_range(0.0..std::f32::consts::TAU);
+    return Vec2::new(angle.cos(), angle.sin());
+}
+
+#[test]
+fn test_rand_norm_vec2() {
+    for _ in 0..100 {
+        assert!((rand_norm_vec2().length() - 1.0).abs() < 1e-5);
+    }
 }
@@ -0,0 +1,57 @@
+use std::fs;
+
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::{audio::AudioConfig, feedback::FeedbackConfig, graphics::GraphicsConfig, input::*, minimap::MinimapConfig, pool::PoolConfig, *};
+
+const SETTINGS_PATH: &str = "settings.ron";
+
+#[derive(Resource, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub bindings: Bindings,
+    pub sticks: StickConfig,
+    pub minimap: MinimapConfig,
+    pub feedback: FeedbackConfig,
+    pub pools: PoolConfig,
+    pub audio: AudioConfig,
+    pub graphics: GraphicsConfig,
+}
+
+impl Settings {
+    pub fn load() -> Settings {
+        match fs::read_to_string(SETTINGS_PATH) {
+            Ok(text) => match ron::from_str::<Settings>(&text) {
+                Ok(mut settings) => {
+                    // actions added since the file was written still get their default keys
+                    settings.bindings.add_missing_defaults();
+                    settings.graphics.sanitize();
+                    settings
+                }
+                Err(e) => {
+                    println!("Failed to parse {}, using defaults: {}", SETTINGS_PATH, e);
+                    Settings::default()
+                }
+            },
+            Err(_) => Settings::default(),
+        }
+    }
+
+    pub fn save(&self) {
+        match ron::ser::to_string_pretty(self, PrettyConfig::default()) {
+            Ok(text) => {
+                if let Err(e) = fs::write(SETTINGS_PATH, text) {
+                    println!("Failed to write {}: {}", SETTINGS_PATH, e);
+                }
+            }
+            Err(e) => println!("Failed to serialize settings: {}", e),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct SettingsMenu {
+    pub open: bool,
+    pub listening: Option<Action>,
+}
@@ -0,0 +1,285 @@
+use bevy::{
+    asset::{AssetLoader, BoxedFuture, LoadContext, LoadState, LoadedAsset},
+    reflect::TypeUuid,
+};
+use serde::Deserialize;
+
+use crate::*;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PlayerTuning {
+    pub dims: Vec2,
+    // slows the player down with the square of their momentum
+    pub drag: f32,
+    pub stats: BaseStats,
+}
+
+impl Default for PlayerTuning {
+    fn default() -> Self {
+        PlayerTuning {
+            dims: vec2(40.0, 45.0),
+            drag: 0.75,
+            stats: BaseStats::default(),
+        }
+    }
+}
+
+/// How an enemy picks its heading each frame: mostly where it was already going, wandering
+/// when the players are far and closing in as they get near.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SteeringTuning {
+    pub momentum: f32,
+    pub wander: f32,
+    pub chase: f32,
+}
+
+impl Default for SteeringTuning {
+    fn default() -> Self {
+        SteeringTuning {
+            momentum: 25.5,
+            wander: 10.5,
+            chase: 5.25,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct EnemyTuning {
+    pub dims: Vec2,
+    // enemies chase harder the further inside this distance the nearest player is
+    pub aggro_range: f32,
+    pub steering: SteeringTuning,
+}
+
+impl Default for EnemyTuning {
+    fn default() -> Self {
+        EnemyTuning {
+            dims: vec2(70.0, 90.0),
+            aggro_range: 700.0,
+            steering: SteeringTuning::default(),
+        }
+    }
+}
+
+/// What each pickup adds to the player. Multipliers are fractions, 0.1 for 10%.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PickupTuning {
+    pub damage_up: f32,
+    pub max_health_up: f32,
+    pub shot_speed_up: f32,
+    pub fire_rate_up: f32,
+    pub piercing_up: f32,
+    pub regen_up: f32,
+    pub crit_chance_up: f32,
+    pub crit_damage_up: f32,
+    pub explosion_radius: f32,
+    pub chain_jumps: f32,
+    pub heart_heal: f32,
+}
+
+impl Default for PickupTuning {
+    fn default() -> Self {
+        PickupTuning {
+            damage_up: 0.1,
+            max_health_up: 20.0,
+            shot_speed_up: 0.1,
+            fire_rate_up: 0.1,
+            piercing_up: 1.0,
+            regen_up: 0.5,
+            crit_chance_up: 0.05,
+            crit_damage_up: 0.5,
+            explosion_radius: 60.0,
+            chain_jumps: 2.0,
+            heart_heal: 25.0,
+        }
+    }
+}
+
+/// Gameplay numbers from `assets/game.tuning.ron`, reloaded whenever the file is saved. Anything
+/// left out of the file keeps its default.
+#[derive(Resource, Deserialize, TypeUuid, Clone, Debug, Default)]
+#[uuid = "5b7d2c19-8e3a-4f60-9a1d-7c4e2b8f6d35"]
+#[serde(default)]
+pub struct Tuning {
+    pub player: PlayerTuning,
+    pub enemy: EnemyTuning,
+    pub pickups: PickupTuning,
+}
+
+// collects every problem instead of stopping at the first, so one save shows them all
+struct Checker(Vec<String>);
+
+impl Checker {
+    fn positive(&mut self, name: &str, value: f32) {
+        if !(value > 0.0) {
+            self.0.push(format!("{} must be above 0, got {}", name, value));
+        }
+    }
+
+    fn non_negative(&mut self, name: &str, value: f32) {
+        if !(value >= 0.0) {
+            self.0.push(format!("{} can't be negative, got {}", name, value));
+        }
+    }
+
+    fn at_most(&mut self, name: &str, value: f32, max: f32) {
+        if !(value <= max) {
+            self.0.push(format!("{} must be at most {}, got {}", name, max, value));
+        }
+    }
+}
+
+impl Tuning {
+    pub fn validate(&self) -> Result<(), String> {
+        let mut check = Checker(vec![]);
+
+        let player = &self.player;
+        check.positive("player.dims.x", player.dims.x);
+        check.positive("player.dims.y", player.dims.y);
+        check.non_negative("player.drag", player.drag);
+
+        let stats = &player.stats;
+        check.non_negative("player.stats.damage", stats.damage);
+        check.non_negative("player.stats.speed", stats.speed);
+        check.positive("player.stats.max_health", stats.max_health);
+        check.positive("player.stats.fire_interval", stats.fire_interval);
+        check.positive("player.stats.shot_speed", stats.shot_speed);
+        check.positive("player.stats.shot_duration", stats.shot_duration);
+        check.positive("player.stats.shot_size", stats.shot_size);
+        check.non_negative("player.stats.piercing", stats.piercing);
+        check.positive("player.stats.mass", stats.mass);
+        check.non_negative("player.stats.regen", stats.regen);
+        check.non_negative("player.stats.crit_chance", stats.crit_chance);
+        check.at_most("player.stats.crit_chance", stats.crit_chance, 1.0);
+        check.non_negative("player.stats.crit_multiplier", stats.crit_multiplier);
+        check.non_negative("player.stats.damage_variance", stats.damage_variance);
+        check.at_most("player.stats.damage_variance", stats.damage_variance, 1.0);
+        check.non_negative("player.stats.knockback", stats.knockback);
+        check.non_negative("player.stats.explosion_radius", stats.explosion_radius);
+        check.non_negative("player.stats.chain_jumps", stats.chain_jumps);
+
+        let enemy = &self.enemy;
+        check.positive("enemy.dims.x", enemy.dims.x);
+        check.positive("enemy.dims.y", enemy.dims.y);
+        check.positive("enemy.aggro_range", enemy.aggro_range);
+        check.non_negative("enemy.steering.momentum", enemy.steering.momentum);
+        check.non_negative("enemy.steering.wander", enemy.steering.wander);
+        check.non_negative("enemy.steering.chase", enemy.steering.chase);
+
+        let pickups = &self.pickups;
+        check.non_negative("pickups.damage_up", pickups.damage_up);
+        check.non_negative("pickups.max_health_up", pickups.max_health_up);
+        check.non_negative("pickups.shot_speed_up", pickups.shot_speed_up);
+        check.non_negative("pickups.fire_rate_up", pickups.fire_rate_up);
+        // taken off the fire interval's multiplier with every pickup, which stops at
+        // MIN_FIRE_INTERVAL_MULTIPLY, so a big step would get there in a couple of pickups
+        check.at_most("pickups.fire_rate_up", pickups.fire_rate_up, 0.5);
+        check.non_negative("pickups.piercing_up", pickups.piercing_up);
+        check.non_negative("pickups.regen_up", pickups.regen_up);
+        check.non_negative("pickups.crit_chance_up", pickups.crit_chance_up);
+        check.non_negative("pickups.crit_damage_up", pickups.crit_damage_up);
+        check.non_negative("pickups.explosion_radius", pickups.explosion_radius);
+        check.non_negative("pickups.chain_jumps", pickups.chain_jumps);
+        check.non_negative("pickups.heart_heal", pickups.heart_heal);
+
+        if check.0.is_empty() {
+            return Ok(());
+        }
+        return Err(check.0.join("\n"));
+    }
+}
+
+#[derive(Default)]
+pub struct TuningLoader;
+
+impl AssetLoader for TuningLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let path = load_context.path().display().to_string();
+            let tuning: Tuning =
+                ron::de::from_bytes(bytes).map_err(|e| bevy::asset::Error::msg(format!("{}: {}", path, e)))?;
+            tuning
+                .validate()
+                .map_err(|e| bevy::asset::Error::msg(format!("{} is invalid:\n{}", path, e)))?;
+            load_context.set_default_asset(LoadedAsset::new(tuning));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tuning.ron"]
+    }
+}
+
+/// Copies the tuning file into the `Tuning` resource whenever it loads. A file that fails to
+/// parse or validate leaves the last good values in place.
+pub fn reload(
+    game: Res<Game>,
+    mut tuning: ResMut<Tuning>,
+    assets: Res<Assets<Tuning>>,
+    asset_server: Res<AssetServer>,
+    mut events: EventReader<AssetEvent<Tuning>>,
+    mut last_state: Local<Option<LoadState>>,
+) {
+    let handle = &game.handles.tuning;
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle: h } | AssetEvent::Modified { handle: h } if h == handle => {
+                if let Some(loaded) = assets.get(handle) {
+                    *tuning = loaded.clone();
+                    println!("Tuning loaded");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // the loader's error has already been logged, this just says what happens next
+    let state = asset_server.get_load_state(handle);
+    if state == LoadState::Failed && *last_state != Some(LoadState::Failed) {
+        println!("Tuning failed to load, keeping the previous values");
+    }
+    *last_state = Some(state);
+}
+
+/// Moves the players' base stats over to the new tuning, keeping what pickups have added.
+pub fn apply(tuning: Res<Tuning>, mut players: Query<&mut Player>) {
+    if !tuning.is_changed() {
+        return;
+    }
+    for mut player in players.iter_mut() {
+        player.stats.rebase(&tuning.player.stats);
+    }
+}
+
+#[test]
+fn defaults_are_valid() {
+    assert_eq!(Tuning::default().validate(), Ok(()));
+}
+
+#[test]
+fn validation_lists_every_problem() {
+    let mut tuning = Tuning::default();
+    tuning.player.drag = -1.0;
+    tuning.enemy.aggro_range = 0.0;
+    let errors = tuning.validate().unwrap_err();
+    assert!(errors.contains("player.drag can't be negative, got -1"), "{}", errors);
+    assert!(errors.contains("enemy.aggro_range must be above 0, got 0"), "{}", errors);
+}
+
+#[test]
+fn bundled_tuning_matches_the_defaults() {
+    let text = std::fs::read_to_string("assets/game.tuning.ron").unwrap();
+    let tuning: Tuning = ron::from_str(&text).unwrap();
+    assert_eq!(tuning.validate(), Ok(()));
+    let defaults = Tuning::default();
+    assert_eq!(format!("{:?}", tuning), format!("{:?}", defaults));
+}
@@ -3,6 +3,9 @@ use super::*;
 use crate::prelude::*;
 
 use bevy::app::AppExit;
+use input::Action;
+use player::MAX_PLAYERS;
+use strum::IntoEnumIterator;
 use bevy_egui::{
     egui::{self},
     EguiContext,
@@ -11,8 +14,14 @@ use bevy_egui::{
 pub fn draw_main_menu(
     mut commands: Commands,
     mut egui_context: ResMut<EguiContext>,
+    mut settings_menu: ResMut<SettingsMenu>,
+    mut game: ResMut<Game>,
+    gamepads: Res<Gamepads>,
     mut exit: EventWriter<AppExit>,
 ) {
+    if settings_menu.open {
+        return;
+    }
     egui::Area::new("Main Menu")
         .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
         .show(egui_context.ctx_mut(), |ui| {
@@ -22,6 +31,16 @@ pub fn draw_main_menu(
             if ui.button("New Game").clicked() {
                 commands.insert_resource(NextState(GameState::Reset));
             }
+            ui.add(egui::Slider::new(&mut game.player_count, 1..=MAX_PLAYERS).text("Players"));
+            ui.checkbox(&mut game.endless, "Endless Arena");
+            if game.player_count > 1 {
+                // player 1 is on keyboard & mouse, the rest need a pad each
+                let pads = gamepads.iter().count();
+                ui.label(format!("Gamepads: {}/{}", pads.min(game.player_count - 1), game.player_count - 1));
+            }
+            if ui.button("Settings").clicked() {
+                settings_menu.open = true;
+            }
             if ui.button("Quit to Desktop").clicked() {
                 exit.send(AppExit);
             }
@@ -31,8 +50,12 @@ pub fn draw_main_menu(
 pub fn draw_pause_menu(
     mut commands: Commands,
     mut egui_context: ResMut<EguiContext>,
+    mut settings_menu: ResMut<SettingsMenu>,
     mut exit: EventWriter<AppExit>,
 ) {
+    if settings_menu.open {
+        return;
+    }
     egui::Area::new("Pause Menu")
         .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
         .show(egui_context.ctx_mut(), |ui| {
@@ -48,16 +71,128 @@ pub fn draw_pause_menu(
             if ui.button("Restart").clicked() {
                 commands.insert_resource(NextState(GameState::Reset));
             }
+            if ui.button("Settings").clicked() {
+                settings_menu.open = true;
+            }
             if ui.button("Quit to Desktop").clicked() {
                 exit.send(AppExit);
             }
         });
 }
 
+fn graphics_settings(ui: &mut egui::Ui, graphics: &mut GraphicsConfig) {
+    ui.horizontal(|ui| {
+        ui.label("Bloom");
+        for preset in [BloomPreset::Off, BloomPreset::Low, BloomPreset::High, BloomPreset::Custom] {
+            ui.selectable_value(&mut graphics.bloom, preset, format!("{:?}", preset));
+        }
+    });
+    if graphics.bloom == BloomPreset::Custom {
+        let bloom = &mut graphics.custom_bloom;
+        ui.add(egui::Slider::new(&mut bloom.threshold, 0.0..=2.0).text("Threshold"));
+        ui.add(egui::Slider::new(&mut bloom.knee, 0.0..=2.0).text("Knee"));
+        ui.add(egui::Slider::new(&mut bloom.intensity, 0.0..=2.0).text("Intensity"));
+        ui.add(egui::Slider::new(&mut bloom.scale, 0.0..=2.0).text("Scale"));
+    }
+    ui.horizontal(|ui| {
+        ui.label("MSAA");
+        for samples in MSAA_SAMPLES {
+            let label = if samples == 1 { "Off".to_string() } else { format!("{}x", samples) };
+            ui.selectable_value(&mut graphics.msaa, samples, label);
+        }
+    });
+    ui.checkbox(&mut graphics.vsync, "VSync");
+    ui.checkbox(&mut graphics.fullscreen, "Fullscreen");
+    ui.add_enabled_ui(!graphics.fullscreen, |ui| {
+        egui::ComboBox::from_label("Resolution")
+            .selected_text(format!("{}x{}", graphics.resolution.0, graphics.resolution.1))
+            .show_ui(ui, |ui| {
+                for resolution in RESOLUTIONS {
+                    ui.selectable_value(&mut graphics.resolution, resolution, format!("{}x{}", resolution.0, resolution.1));
+                }
+            });
+    });
+    ui.add(egui::Slider::new(&mut graphics.ui_scale, UI_SCALE_RANGE).step_by(0.25).text("UI Scale"));
+    ui.add(egui::Slider::new(&mut graphics.fps_cap, 0..=240).text("FPS Cap (0 = none)"));
+}
+
+pub fn draw_settings(
+    mut egui_context: ResMut<EguiContext>,
+    mut settings_menu: ResMut<SettingsMenu>,
+    mut settings: ResMut<Settings>,
+) {
+    if !settings_menu.open {
+        return;
+    }
+    egui::Window::new("Settings")
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.label("Controls");
+            egui::Grid::new("bindings").striped(true).show(ui, |ui| {
+                for action in Action::iter() {
+                    ui.label(action.label());
+                    ui.horizontal(|ui| {
+                        let bindings = settings.bindings.get(action).to_vec();
+                        for (i, binding) in bindings.iter().enumerate() {
+                            if ui.button(binding.to_string()).on_hover_text("Click to remove").clicked() {
+                                settings.bindings.remove(action, i);
+                            }
+                        }
+                        if settings_menu.listening == Some(action) {
+                            ui.label("Press a key or button... (Esc to cancel)");
+                        } else if ui.button("+").clicked() {
+                            settings_menu.listening = Some(action);
+                        }
+                    });
+                    ui.end_row();
+                }
+            });
+            ui.separator();
+            ui.label("Gamepad");
+            ui.add(egui::Slider::new(&mut settings.sticks.move_deadzone, 0.0..=0.9).text("Move Deadzone"));
+            ui.add(egui::Slider::new(&mut settings.sticks.aim_deadzone, 0.0..=0.9).text("Aim Deadzone"));
+            ui.add(egui::Slider::new(&mut settings.sticks.aim_radius, 50.0..=400.0).text("Aim Distance"));
+            ui.separator();
+            ui.label("HUD");
+            ui.checkbox(&mut settings.minimap.show_minimap, "Minimap");
+            ui.add(egui::Slider::new(&mut settings.minimap.size, 60.0..=240.0).text("Minimap Size"));
+            ui.checkbox(&mut settings.minimap.show_arrows, "Off-screen Arrows");
+            ui.separator();
+            ui.label("Accessibility");
+            ui.checkbox(&mut settings.feedback.damage_numbers, "Damage Numbers");
+            ui.checkbox(&mut settings.feedback.hit_flash, "Hit Flash");
+            ui.separator();
+            ui.label("Audio");
+            ui.add(egui::Slider::new(&mut settings.audio.master, 0.0..=1.0).text("Master Volume"));
+            ui.add(egui::Slider::new(&mut settings.audio.music, 0.0..=1.0).text("Music Volume"));
+            ui.add(egui::Slider::new(&mut settings.audio.sfx, 0.0..=1.0).text("Effects Volume"));
+            ui.separator();
+            ui.label("Graphics");
+            graphics_settings(ui, &mut settings.graphics);
+            ui.horizontal(|ui| {
+                if ui.button("Reset to Defaults").clicked() {
+                    settings.bindings = default();
+                    settings.sticks = default();
+                    settings.minimap = default();
+                    settings.feedback = default();
+                    settings.audio = default();
+                    settings.graphics = default();
+                }
+                if ui.button("Back").clicked() {
+                    settings.save();
+                    settings_menu.open = false;
+                    settings_menu.listening = None;
+                }
+            });
+        });
+}
+
 pub fn draw_game_over(
     mut commands: Commands,
     mut egui_context: ResMut<EguiContext>,
-    state: Res<Game>,
+    players: Query<&Player>,
 ) {
     egui::Area::new("Game Over")
         .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
@@ -66,61 +201,176 @@ pub fn draw_game_over(
             let visuals = &mut ui.style_mut().visuals;
             visuals.widgets.noninteractive.fg_stroke.color = egui::Color32::WHITE;
             ui.label("You Suck");
-            ui.label(format!("Your Score: {}", state.player.score));
+            ui.label(format!("Your Score: {}", players.iter().map(|p| p.score).sum::<i32>()));
+            if players.iter().count() > 1 {
+                for player in sorted_players(&players) {
+                    ui.colored_label(
+                        to_egui_color(player.color),
+                        format!("P{}: {} ({} crits)", player.slot + 1, player.score, player.crits),
+                    );
+                }
+            } else {
+                ui.label(format!("Crits: {}", players.iter().map(|p| p.crits).sum::<i32>()));
+            }
             if ui.button("Restart").clicked() {
                 commands.insert_resource(NextState(GameState::Reset));
             }
         });
 }
 
-pub fn draw_hud(mut egui_context: ResMut<EguiContext>, game: Res<Game>, windows: Res<Windows>, mut bloom_settings: Query<&mut BloomSettings>,) {
+fn sorted_players<'a>(players: &'a Query<&Player>) -> Vec<&'a Player> {
+    let mut sorted: Vec<&Player> = players.iter().collect();
+    sorted.sort_by_key(|p| p.slot);
+    return sorted;
+}
+
+pub fn to_egui_color(color: Color) -> egui::Color32 {
+    let [r, g, b, a] = color.as_rgba_f32();
+    return egui::Color32::from_rgba_unmultiplied(
+        (r * 255.0) as u8,
+        (g * 255.0) as u8,
+        (b * 255.0) as u8,
+        (a * 255.0) as u8,
+    );
+}
+
+/// Converts a world position to egui points, which start at the top left and are scaled
+/// by `ui_scale` (the egui scale factor) on top of the window's own.
+pub fn world_to_ui(
+    window: &Window,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    ui_scale: f32,
+    pos: Vec2,
+) -> Option<egui::Pos2> {
+    return world_to_screen(window, camera, camera_transform, pos)
+        .map(|p| egui::pos2(p.x / ui_scale, (window.height() - p.y) / ui_scale));
+}
+
+pub fn hud_style(ui: &mut egui::Ui) {
+    let visuals = &mut ui.style_mut().visuals;
+    visuals.extreme_bg_color = egui::Color32::DARK_GRAY;
+    visuals.faint_bg_color = egui::Color32::RED;
+    visuals.widgets.noninteractive.bg_fill = egui::Color32::RED;
+    visuals.widgets.noninteractive.fg_stroke.color = egui::Color32::WHITE;
+    visuals.widgets.noninteractive.bg_stroke.color = egui::Color32::RED;
+    visuals.selection.bg_fill = egui::Color32::RED;
+}
+
+/// Health in red with any shield stacked after it in blue. While overhealed the bar
+/// stretches to fit, so the shield never hides missing health.
+fn health_bar(ui: &mut egui::Ui, health: f32, shield: f32, max_health: f32) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(100.0, 14.0), egui::Sense::hover());
+    let painter = ui.painter();
+    painter.rect_filled(rect, 2.0, egui::Color32::DARK_GRAY);
+
+    let total = max_health.max(health + shield).max(1.0);
+    let width = |value: f32| rect.width() * (value / total).clamp(0.0, 1.0);
+    let health_rect = egui::Rect::from_min_size(rect.min, egui::vec2(width(health), rect.height()));
+    painter.rect_filled(health_rect, 2.0, egui::Color32::RED);
+    let shield_rect = egui::Rect::from_min_size(
+        egui::pos2(health_rect.max.x, rect.min.y),
+        egui::vec2(width(shield), rect.height()),
+    );
+    painter.rect_filled(shield_rect, 2.0, egui::Color32::from_rgb(80, 200, 255));
+
+    let mut text = format!("{:.0}/{:.0}", health, max_health);
+    if shield >= 1.0 {
+        text += &format!(" +{:.0}", shield);
+    }
+    painter.text(
+        rect.center(),
+        egui::Align2::CENTER_CENTER,
+        text,
+        egui::FontId::proportional(10.0),
+        egui::Color32::WHITE,
+    );
+}
+
+pub fn draw_hud(
+    mut egui_context: ResMut<EguiContext>,
+    game: Res<Game>,
+    dungeon: Option<Res<Dungeon>>,
+    windows: Res<Windows>,
+    players: Query<&Player>,
+    bullet_pool: Res<Pool<Bullet>>,
+    arc_pool: Res<Pool<LightningArc>>,
+    flash_pool: Res<Pool<ExplosionFlash>>,
+    particle_pool: Res<Pool<Particle>>,
+    dev: Res<DevTools>,
+) {
+    // one panel per player, clockwise from the top left corner
+    let corners = [
+        (egui::Align2::LEFT_TOP, [10.0, 10.0]),
+        (egui::Align2::RIGHT_TOP, [-10.0, 10.0]),
+        (egui::Align2::RIGHT_BOTTOM, [-10.0, -10.0]),
+        (egui::Align2::LEFT_BOTTOM, [10.0, -10.0]),
+    ];
+    for player in players.iter() {
+        let (align, offset) = corners[player.slot % corners.len()];
+        egui::Area::new(format!("hud p{}", player.slot + 1))
+            .anchor(align, offset)
+            .show(egui_context.ctx_mut(), |ui| {
+                hud_style(ui);
+                ui.colored_label(to_egui_color(player.color), format!("P{}", player.slot + 1));
+                ui.label(format!("Score: {:?}", player.score));
+                health_bar(ui, player.health, player.shield, player.stats.max_health.value());
+                if player.downed {
+                    ui.label(format!("DOWN - reviving {:.0}%", player.revive_progress * 100.0));
+                }
+            });
+    }
+
     egui::Area::new("hud")
-        .anchor(egui::Align2::LEFT_TOP, [10.0, 10.0])
+        .anchor(egui::Align2::CENTER_TOP, [0.0, 10.0])
         .show(egui_context.ctx_mut(), |ui| {
-            let visuals = &mut ui.style_mut().visuals;
-            visuals.extreme_bg_color = egui::Color32::DARK_GRAY;
-            visuals.faint_bg_color = egui::Color32::RED;
-            visuals.widgets.noninteractive.bg_fill = egui::Color32::RED;
-            visuals.widgets.noninteractive.fg_stroke.color = egui::Color32::WHITE;
-            visuals.widgets.noninteractive.bg_stroke.color = egui::Color32::RED;
-            visuals.selection.bg_fill = egui::Color32::RED;
-            ui.label(format!("Score: {:?}", game.player.score));
-            ui.add(
-                egui::ProgressBar::new(
-                    game.player.health as f32 / game.player.stats.max_health.value() as f32,
-                )
-                .text(format!(
-                    "{}/{}",
-                    game.player.health,
-                    game.player.stats.max_health.value()
-                ))
-                .desired_width(100.0)
-                .animate(false),
-            );
-            ui.label(format!("Wave: {:?}", game.wave));
+            hud_style(ui);
+            if let Some(dungeon) = dungeon {
+                if dungeon.endless {
+                    ui.label(format!("Wave: {:?}", dungeon.rooms[0].wave));
+                } else {
+                    ui.label(format!("Floor: {} (seed {})", dungeon.floor, dungeon.seed));
+                    if let Some(room) = dungeon.active_room.map(|i| &dungeon.rooms[i]) {
+                        ui.label(format!("{:?} room - waves left: {}", room.kind, room.waves_left));
+                    }
+                }
+            }
             ui.label(format!("Kills: {:?}", game.kills));
 
-            let debug = true;
-            if debug {
+            if dev.overlay {
+                ui.label("F2: colliders, middle click: inspect");
                 if let Some(window) = windows.get_primary() {
                     ui.label(format!("Mouse: {:?}", window.cursor_position()));
                     ui.label(format!("Mouse World: {:?}", game.mouse_world_pos));
                     ui.label(format!("Mouse Rel: {:?}", game.mouse_rel_pos));
                     ui.label(format!("Window: {:?}", game.window_size));
-
-                    let mut bloom_settings = bloom_settings.single_mut();
-                    ui.add(egui::Slider::new(&mut bloom_settings.knee, 0.0..=2.0).text("Knee"));
-                    ui.add(egui::Slider::new(&mut bloom_settings.threshold, 0.0..=2.0).text("Threshold"));
-                    ui.add(egui::Slider::new(&mut bloom_settings.intensity, 0.0..=2.0).text("Intensity"));
-                    ui.add(egui::Slider::new(&mut bloom_settings.scale, 0.0..=2.0).text("Scale"));
                 }
-                ui.label(format!("Player: {:?}", game.player.position));
+                if let Some(player) = players.iter().find(|p| p.slot == 0) {
+                    ui.label(format!("Player: {:?}", player.position));
 
-                ui.label(format!("Damage: {}", game.player.stats.damage));
-                ui.label(format!("Shot Speed: {}", game.player.stats.shot_speed));
-                ui.label(format!("Shot Duration: {}", game.player.stats.shot_duration));
-                ui.label(format!("Shot Size: {}", game.player.stats.shot_size));
-                ui.label(format!("Fire Interval: {}", game.player.stats.fire_interval));
+                    ui.label(format!("Damage: {}", player.stats.damage));
+                    ui.label(format!("Shot Speed: {}", player.stats.shot_speed));
+                    ui.label(format!("Shot Duration: {}", player.stats.shot_duration));
+                    ui.label(format!("Shot Size: {}", player.stats.shot_size));
+                    ui.label(format!("Fire Interval: {}", player.stats.fire_interval));
+                    ui.label(format!("Regen: {}", player.stats.regen));
+                    ui.label(format!("Crit Chance: {}", player.stats.crit_chance));
+                    ui.label(format!("Crit Multiplier: {}", player.stats.crit_multiplier));
+                    ui.label(format!("Explosion Radius: {}", player.stats.explosion_radius));
+                    ui.label(format!("Chain Jumps: {}", player.stats.chain_jumps));
+                    ui.label(format!("Weapon: {:?}", player.weapon));
+                }
+                for (name, stats) in [
+                    ("Bullets", bullet_pool.stats()),
+                    ("Arcs", arc_pool.stats()),
+                    ("Flashes", flash_pool.stats()),
+                    ("Particles", particle_pool.stats()),
+                ] {
+                    ui.label(format!(
+                        "{} pool: {} live, {} free, {} spawned, {} reused, {} refused",
+                        name, stats.live, stats.free, stats.spawned, stats.reused, stats.refused
+                    ));
+                }
             }
         });
 }